@@ -22,11 +22,7 @@ async fn hello_world(homeserver_url: Uri, room_alias: &RoomAliasId) -> anyhow::R
             &room_id,
             "1",
             &AnyMessageEventContent::RoomMessage(MessageEventContent::Text(
-                TextMessageEventContent {
-                    body: "Hello World!".to_owned(),
-                    formatted: None,
-                    relates_to: None,
-                },
+                TextMessageEventContent::plain("Hello World!"),
             )),
         ))
         .await?;
@@ -59,6 +59,23 @@ pub struct MessageEvent<C: MessageEventContent> {
     pub unsigned: Unsigned,
 }
 
+impl<C: MessageEventContent> MessageEvent<C> {
+    /// Get the boolean flag stored under `key` in `unsigned`, see `Unsigned::flag`.
+    pub fn unsigned_flag(&self, key: &str) -> Option<bool> {
+        self.unsigned.flag(key)
+    }
+
+    /// Set the boolean flag `key` in `unsigned`, see `Unsigned::set_flag`.
+    pub fn set_unsigned_flag(&mut self, key: impl Into<String>, value: bool) {
+        self.unsigned.set_flag(key, value)
+    }
+
+    /// Remove the boolean flag `key` from `unsigned`, see `Unsigned::remove_flag`.
+    pub fn remove_unsigned_flag(&mut self, key: &str) -> Option<bool> {
+        self.unsigned.remove_flag(key)
+    }
+}
+
 /// A message event without a `room_id`.
 ///
 /// `SyncMessageEvent` implements the comparison traits using only
@@ -82,6 +99,23 @@ pub struct SyncMessageEvent<C: MessageEventContent> {
     pub unsigned: Unsigned,
 }
 
+impl<C: MessageEventContent> SyncMessageEvent<C> {
+    /// Get the boolean flag stored under `key` in `unsigned`, see `Unsigned::flag`.
+    pub fn unsigned_flag(&self, key: &str) -> Option<bool> {
+        self.unsigned.flag(key)
+    }
+
+    /// Set the boolean flag `key` in `unsigned`, see `Unsigned::set_flag`.
+    pub fn set_unsigned_flag(&mut self, key: impl Into<String>, value: bool) {
+        self.unsigned.set_flag(key, value)
+    }
+
+    /// Remove the boolean flag `key` from `unsigned`, see `Unsigned::remove_flag`.
+    pub fn remove_unsigned_flag(&mut self, key: &str) -> Option<bool> {
+        self.unsigned.remove_flag(key)
+    }
+}
+
 /// A redacted message event.
 ///
 /// `RedactedMessageEvent` implements the comparison traits using only
@@ -117,16 +117,16 @@
 #![allow(clippy::unnested_or_patterns)]
 #![allow(clippy::unknown_clippy_lints)]
 
-use std::fmt::Debug;
+use std::{collections::BTreeMap, fmt::Debug};
 
 use js_int::Int;
 use ruma_common::Raw;
 use ruma_identifiers::{EventEncryptionAlgorithm, RoomId};
 use serde::{
     de::{self, IgnoredAny},
-    Deserialize, Serialize,
+    Deserialize, Deserializer, Serialize,
 };
-use serde_json::value::RawValue as RawJsonValue;
+use serde_json::{value::RawValue as RawJsonValue, Value as JsonValue};
 
 use self::room::redaction::{RedactionEvent, SyncRedactionEvent};
 
@@ -172,6 +172,8 @@ pub mod presence;
 pub mod push_rules;
 pub mod reaction;
 pub mod receipt;
+#[cfg(feature = "unstable-pre-spec")]
+pub mod relation;
 pub mod room;
 pub mod room_key;
 pub mod room_key_request;
@@ -216,18 +218,64 @@ pub struct Unsigned {
     /// is the same one which sent it.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub transaction_id: Option<String>,
+
+    /// Any other keys, such as client-local vendor-prefixed flags.
+    ///
+    /// Keys that are defined by the specification but not modelled by this type, like
+    /// `prev_content` or `redacted_because`, are dropped when deserializing.
+    #[serde(flatten, deserialize_with = "deserialize_custom_unsigned")]
+    pub custom: BTreeMap<String, JsonValue>,
 }
 
 impl Unsigned {
-    /// Whether this unsigned data is empty (all fields are `None`).
+    /// Whether this unsigned data is empty (all fields are `None` and `custom` is empty).
     ///
     /// This method is used to determine whether to skip serializing the
     /// `unsigned` field in room events. Do not use it to determine whether
     /// an incoming `unsigned` field was present - it could still have been
     /// present but contained none of the known fields.
     pub fn is_empty(&self) -> bool {
-        self.age.is_none() && self.transaction_id.is_none()
+        self.age.is_none() && self.transaction_id.is_none() && self.custom.is_empty()
+    }
+
+    /// Get the boolean flag stored under `key` in `custom`.
+    ///
+    /// Returns `None` if the key is absent or its value is not a boolean. `key` should be
+    /// vendor-prefixed, e.g. `com.example.marked_unread`.
+    pub fn flag(&self, key: &str) -> Option<bool> {
+        self.custom.get(key).and_then(JsonValue::as_bool)
+    }
+
+    /// Set the boolean flag `key` in `custom`, leaving all other unsigned data untouched.
+    pub fn set_flag(&mut self, key: impl Into<String>, value: bool) {
+        self.custom.insert(key.into(), JsonValue::Bool(value));
     }
+
+    /// Remove the boolean flag `key` from `custom`, returning its previous value.
+    ///
+    /// Returns `None` and leaves `custom` untouched if the key is absent or its value is not a
+    /// boolean.
+    pub fn remove_flag(&mut self, key: &str) -> Option<bool> {
+        let value = self.flag(key)?;
+        self.custom.remove(key);
+        Some(value)
+    }
+}
+
+/// Keys of `unsigned` that are defined by the specification but don't have a field in `Unsigned`.
+const SPECIFIED_UNSIGNED_KEYS: &[&str] = &["invite_room_state", "prev_content", "redacted_because"];
+
+/// Deserializes `Unsigned::custom`, leaving out the keys in `SPECIFIED_UNSIGNED_KEYS`.
+fn deserialize_custom_unsigned<'de, D>(
+    deserializer: D,
+) -> Result<BTreeMap<String, JsonValue>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(BTreeMap::<String, JsonValue>::deserialize(deserializer)?
+        .into_iter()
+        .filter(|(key, _)| !SPECIFIED_UNSIGNED_KEYS.contains(&key.as_str()))
+        .collect())
 }
 
 /// Extra information about a redacted event that is not incorporated into the event's
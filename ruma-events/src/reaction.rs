@@ -1,6 +1,6 @@
 //! Types for the *m.reaction* event.
 
-use std::convert::TryFrom;
+use std::{collections::BTreeMap, convert::TryFrom};
 
 use crate::{
     room::relationships::{Annotation, RelatesToJsonRepr, RelationJsonRepr},
@@ -65,3 +65,164 @@ impl ReactionEventContent {
         ReactionEventContent { relation: Relation { event_id, emoji } }
     }
 }
+
+/// Returns the text to display for the given reaction key.
+///
+/// If the key is a shortcode like `:thumbsup:` and the `emoji` feature is enabled, known
+/// shortcodes are replaced by the corresponding emoji. Any other key is returned unchanged.
+pub fn reaction_display(key: &str) -> String {
+    #[cfg(feature = "emoji")]
+    {
+        if key.len() > 2 && key.starts_with(':') && key.ends_with(':') {
+            let shortcode = &key[1..key.len() - 1];
+            if let Ok(idx) = EMOJI_SHORTCODES.binary_search_by_key(&shortcode, |(code, _)| code) {
+                return EMOJI_SHORTCODES[idx].1.to_owned();
+            }
+        }
+    }
+
+    key.to_owned()
+}
+
+/// How reaction keys are compared when counting reactions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReactionKeyNormalization {
+    /// Keys are only equal if they are exactly the same, e.g. `👍` and `👍🏽` are distinct.
+    Exact,
+
+    /// Skin tone modifiers are ignored, e.g. `👍` and `👍🏽` are the same.
+    IgnoreSkinTone,
+
+    /// Variation selectors are ignored, e.g. `❤` and `❤️` are the same.
+    IgnoreVariationSelectors,
+}
+
+impl Default for ReactionKeyNormalization {
+    fn default() -> Self {
+        Self::Exact
+    }
+}
+
+impl ReactionKeyNormalization {
+    /// Normalizes the given reaction key according to this policy.
+    pub fn normalize(self, key: &str) -> String {
+        match self {
+            Self::Exact => key.to_owned(),
+            Self::IgnoreSkinTone => key.chars().filter(|c| !is_skin_tone_modifier(*c)).collect(),
+            Self::IgnoreVariationSelectors => {
+                key.chars().filter(|c| !is_variation_selector(*c)).collect()
+            }
+        }
+    }
+}
+
+fn is_skin_tone_modifier(c: char) -> bool {
+    ('\u{1F3FB}'..='\u{1F3FF}').contains(&c)
+}
+
+fn is_variation_selector(c: char) -> bool {
+    c == '\u{FE0E}' || c == '\u{FE0F}'
+}
+
+/// Counts the reactions with the given keys, comparing keys according to `normalization`.
+///
+/// The returned map is keyed by the normalized keys.
+pub fn count_reactions<'a>(
+    keys: impl IntoIterator<Item = &'a str>,
+    normalization: ReactionKeyNormalization,
+) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for key in keys {
+        *counts.entry(normalization.normalize(key)).or_insert(0) += 1;
+    }
+
+    counts
+}
+
+/// Common emoji shortcodes and the emoji they stand for, sorted by shortcode.
+#[cfg(feature = "emoji")]
+const EMOJI_SHORTCODES: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("100", "💯"),
+    ("angry", "😠"),
+    ("clap", "👏"),
+    ("confused", "😕"),
+    ("cry", "😢"),
+    ("eyes", "👀"),
+    ("fire", "🔥"),
+    ("grin", "😁"),
+    ("heart", "❤️"),
+    ("joy", "😂"),
+    ("laughing", "😆"),
+    ("ok_hand", "👌"),
+    ("party_popper", "🎉"),
+    ("pray", "🙏"),
+    ("rocket", "🚀"),
+    ("slightly_smiling_face", "🙂"),
+    ("smile", "😄"),
+    ("sob", "😭"),
+    ("tada", "🎉"),
+    ("thinking", "🤔"),
+    ("thumbsdown", "👎"),
+    ("thumbsup", "👍"),
+    ("wave", "👋"),
+    ("white_check_mark", "✅"),
+    ("wink", "😉"),
+    ("x", "❌"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::{count_reactions, reaction_display, ReactionKeyNormalization};
+
+    #[test]
+    #[cfg(feature = "emoji")]
+    fn known_shortcode() {
+        assert_eq!(reaction_display(":thumbsup:"), "👍");
+    }
+
+    #[test]
+    fn unknown_shortcode() {
+        assert_eq!(reaction_display(":not_an_emoji:"), ":not_an_emoji:");
+    }
+
+    #[test]
+    fn emoji_key() {
+        assert_eq!(reaction_display("🦛"), "🦛");
+    }
+
+    #[test]
+    #[cfg(feature = "emoji")]
+    fn shortcodes_sorted() {
+        assert!(super::EMOJI_SHORTCODES.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    const KEYS: &[&str] = &["👍", "👍\u{1F3FD}", "❤", "❤\u{FE0F}"];
+
+    #[test]
+    fn count_exact() {
+        let counts = count_reactions(KEYS.iter().copied(), ReactionKeyNormalization::Exact);
+        assert_eq!(counts.len(), 4);
+        assert_eq!(counts["👍\u{1F3FD}"], 1);
+    }
+
+    #[test]
+    fn count_ignoring_skin_tone() {
+        let counts =
+            count_reactions(KEYS.iter().copied(), ReactionKeyNormalization::IgnoreSkinTone);
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts["👍"], 2);
+    }
+
+    #[test]
+    fn count_ignoring_variation_selectors() {
+        let counts = count_reactions(
+            KEYS.iter().copied(),
+            ReactionKeyNormalization::IgnoreVariationSelectors,
+        );
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts["❤"], 2);
+        assert_eq!(counts["👍"], 1);
+    }
+}
@@ -0,0 +1,578 @@
+//! Types for bundled aggregations of event relationships.
+//!
+//! When an event has been related to by other events, the server may include an aggregation of
+//! those relations in the `unsigned` data of the related event. This module contains types for
+//! those aggregations.
+//!
+//! MSC for aggregations: https://github.com/matrix-org/matrix-doc/pull/2675
+
+use std::{
+    convert::TryFrom,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use js_int::UInt;
+use ruma_identifiers::{EventId, UserId};
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::reaction::{ReactionEventContent, ReactionKeyNormalization};
+
+/// An event referencing the event the aggregation is bundled with.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ReferencedEvent {
+    /// The ID of the referencing event.
+    pub event_id: EventId,
+}
+
+impl ReferencedEvent {
+    /// Creates a new `ReferencedEvent` with the given event ID.
+    pub fn new(event_id: EventId) -> Self {
+        Self { event_id }
+    }
+}
+
+/// A chunk of references.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ReferenceChunk {
+    /// A batch of events referencing the event the aggregation is bundled with.
+    pub chunk: Vec<ReferencedEvent>,
+}
+
+impl ReferenceChunk {
+    /// Creates a new `ReferenceChunk` with the given chunk.
+    pub fn new(chunk: Vec<ReferencedEvent>) -> Self {
+        Self { chunk }
+    }
+}
+
+/// A bundled reaction, i.e. an aggregation of the reactions to an event with the same key.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BundledReaction {
+    /// The key (emoji) used for the reactions.
+    pub key: String,
+
+    /// Time of the bundled reaction being sent.
+    ///
+    /// Some servers send this as a string, which is accepted too.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_opt_uint_or_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub origin_server_ts: Option<UInt>,
+
+    /// The number of reactions with this key.
+    pub count: UInt,
+
+    /// The users that reacted with this key, e.g. for a "who reacted" tooltip.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<UserId>,
+}
+
+impl BundledReaction {
+    /// Creates a new `BundledReaction`.
+    pub fn new(key: String, origin_server_ts: Option<UInt>, count: UInt) -> Self {
+        Self { key, origin_server_ts, count, users: Vec::new() }
+    }
+
+    /// Whether this bundled reaction has the same key, count and users as `other`.
+    ///
+    /// Unlike a full comparison, this ignores `origin_server_ts`, so it can be used to detect
+    /// changes of the reactions to an event between syncs.
+    pub fn eq_ignoring_ts(&self, other: &Self) -> bool {
+        self.key == other.key && self.count == other.count && self.users == other.users
+    }
+
+    /// Creates the content of an individual reaction to the event with the given ID, with the key
+    /// of this bundled reaction.
+    pub fn to_reaction_content(&self, event_id: EventId) -> ReactionEventContent {
+        ReactionEventContent::new(event_id, self.key.clone())
+    }
+}
+
+/// A chunk of bundled reactions.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct AnnotationChunk {
+    /// The bundled reactions, one per key.
+    pub chunk: Vec<BundledReaction>,
+}
+
+impl AnnotationChunk {
+    /// Creates a new `AnnotationChunk` with the given chunk.
+    pub fn new(chunk: Vec<BundledReaction>) -> Self {
+        Self { chunk }
+    }
+
+    /// Aggregates the given reactions, given as pairs of the reacting user and the key.
+    ///
+    /// Keys are compared according to `normalization`, like in
+    /// [`count_reactions`](crate::reaction::count_reactions), and the bundled reactions are keyed
+    /// by the normalized keys. They are in the order in which their keys first appear. Each of
+    /// them counts all reactions with its key and lists every user that reacted with it once.
+    pub fn from_reactions(
+        reactions: impl IntoIterator<Item = (UserId, String)>,
+        normalization: ReactionKeyNormalization,
+    ) -> Self {
+        let mut chunk: Vec<BundledReaction> = Vec::new();
+
+        for (user, key) in reactions {
+            let key = normalization.normalize(&key);
+            let reaction = match chunk.iter().position(|reaction| reaction.key == key) {
+                Some(idx) => &mut chunk[idx],
+                None => {
+                    chunk.push(BundledReaction::new(key, None, UInt::from(0_u32)));
+                    chunk.last_mut().unwrap()
+                }
+            };
+
+            reaction.count = reaction.count.saturating_add(UInt::from(1_u32));
+            if !reaction.users.contains(&user) {
+                reaction.users.push(user);
+            }
+        }
+
+        Self { chunk }
+    }
+
+    /// Adjusts the count of the bundled reaction with the given key by `delta`, e.g. `1` when a
+    /// reaction was added or `-1` when one was redacted.
+    ///
+    /// A bundled reaction is added at the end if there is none with this key yet, and removed if
+    /// its count drops to zero. Counts never go below zero. The users of the bundled reactions
+    /// are left unchanged.
+    pub fn apply_delta(&mut self, key: &str, delta: i64) {
+        let magnitude =
+            UInt::try_from(delta.checked_abs().unwrap_or(i64::MAX)).unwrap_or(UInt::MAX);
+
+        match self.chunk.iter().position(|reaction| reaction.key == key) {
+            Some(idx) => {
+                let count = if delta >= 0 {
+                    self.chunk[idx].count.saturating_add(magnitude)
+                } else {
+                    self.chunk[idx].count.saturating_sub(magnitude)
+                };
+
+                if count > UInt::from(0_u32) {
+                    self.chunk[idx].count = count;
+                } else {
+                    self.chunk.remove(idx);
+                }
+            }
+            None if delta > 0 => {
+                self.chunk.push(BundledReaction::new(key.to_owned(), None, magnitude));
+            }
+            None => {}
+        }
+    }
+}
+
+/// The latest replacement of the event the aggregation is bundled with.
+///
+/// MSC for edits: https://github.com/matrix-org/matrix-doc/pull/2676
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BundledReplacement {
+    /// The ID of the replacing event.
+    pub event_id: EventId,
+
+    /// Time in milliseconds since the Unix epoch at which the replacing event was sent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin_server_ts: Option<UInt>,
+
+    /// The user that sent the replacing event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender: Option<UserId>,
+}
+
+impl BundledReplacement {
+    /// Creates a new `BundledReplacement` with the given event ID.
+    pub fn new(event_id: EventId) -> Self {
+        Self { event_id, origin_server_ts: None, sender: None }
+    }
+
+    /// The time at which the replacing event was sent, converted from `origin_server_ts`.
+    ///
+    /// This can be used to show when a message was edited.
+    pub fn timestamp(&self) -> Option<SystemTime> {
+        self.origin_server_ts.map(|ts| UNIX_EPOCH + Duration::from_millis(ts.into()))
+    }
+}
+
+/// The bundled aggregations of the relations to an event, i.e. the `m.relations` in its
+/// `unsigned` data.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Relations {
+    /// Annotation relations, i.e. reactions.
+    #[serde(rename = "m.annotation", skip_serializing_if = "Option::is_none")]
+    pub annotation: Option<AnnotationChunk>,
+
+    /// Reference relations.
+    #[serde(rename = "m.reference", skip_serializing_if = "Option::is_none")]
+    pub reference: Option<ReferenceChunk>,
+
+    /// The latest replacement, i.e. edit.
+    #[serde(rename = "m.replace", skip_serializing_if = "Option::is_none")]
+    pub replace: Option<BundledReplacement>,
+}
+
+impl Relations {
+    /// Creates a new empty `Relations`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Merges the aggregations from `other`, e.g. received in a later batch of a sync, into this
+    /// one.
+    ///
+    /// Both are assumed to aggregate distinct relations. Bundled reactions with the same key are
+    /// combined by adding up their counts and joining their users, keeping the later timestamp.
+    /// References are concatenated without duplicates. Of the two replacements, the one with the
+    /// later timestamp is kept, or the one from `other` if that can't be decided.
+    pub fn merge(&mut self, other: Relations) {
+        if let Some(other) = other.annotation {
+            let annotation = self.annotation.get_or_insert_with(AnnotationChunk::default);
+            for reaction in other.chunk {
+                match annotation.chunk.iter_mut().find(|r| r.key == reaction.key) {
+                    Some(existing) => {
+                        existing.count = existing.count.saturating_add(reaction.count);
+                        existing.origin_server_ts =
+                            existing.origin_server_ts.max(reaction.origin_server_ts);
+                        for user in reaction.users {
+                            if !existing.users.contains(&user) {
+                                existing.users.push(user);
+                            }
+                        }
+                    }
+                    None => annotation.chunk.push(reaction),
+                }
+            }
+        }
+
+        if let Some(other) = other.reference {
+            let reference = self.reference.get_or_insert_with(ReferenceChunk::default);
+            for event in other.chunk {
+                if !reference.chunk.iter().any(|e| e.event_id == event.event_id) {
+                    reference.chunk.push(event);
+                }
+            }
+        }
+
+        if let Some(other) = other.replace {
+            let is_newer = match &self.replace {
+                Some(current) => match (current.origin_server_ts, other.origin_server_ts) {
+                    (Some(current_ts), Some(other_ts)) => other_ts >= current_ts,
+                    _ => true,
+                },
+                None => true,
+            };
+
+            if is_newer {
+                self.replace = Some(other);
+            }
+        }
+    }
+}
+
+fn deserialize_opt_uint_or_string<'de, D>(deserializer: D) -> Result<Option<UInt>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum UIntOrString {
+        UInt(UInt),
+        String(String),
+    }
+
+    match Option::<UIntOrString>::deserialize(deserializer)? {
+        Some(UIntOrString::UInt(uint)) => Ok(Some(uint)),
+        Some(UIntOrString::String(string)) => string.parse().map(Some).map_err(de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::TryFrom,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use js_int::{uint, UInt};
+    use ruma_identifiers::{event_id, user_id};
+    use serde_json::{
+        from_str as from_json_str, from_value as from_json_value, json, to_value as to_json_value,
+    };
+
+    use crate::reaction::{count_reactions, ReactionKeyNormalization};
+
+    use super::{
+        AnnotationChunk, BundledReaction, BundledReplacement, ReferenceChunk, ReferencedEvent,
+        Relations,
+    };
+
+    #[test]
+    fn reference_chunk_serialize_deserialize() {
+        let chunk = ReferenceChunk::new(vec![
+            ReferencedEvent::new(event_id!("$first:localhost")),
+            ReferencedEvent::new(event_id!("$second:localhost")),
+        ]);
+        let json = json!({
+            "chunk": [
+                { "event_id": "$first:localhost" },
+                { "event_id": "$second:localhost" },
+            ]
+        });
+
+        assert_eq!(to_json_value(&chunk).unwrap(), json);
+
+        let chunk = from_json_value::<ReferenceChunk>(json).unwrap();
+        assert_eq!(chunk.chunk.len(), 2);
+        assert_eq!(chunk.chunk[1].event_id, event_id!("$second:localhost"));
+    }
+
+    #[test]
+    fn bundled_reaction_integer_ts() {
+        let reaction = from_json_str::<BundledReaction>(
+            r#"{ "key": "👍", "origin_server_ts": 1604001234567, "count": 3 }"#,
+        )
+        .unwrap();
+
+        assert_eq!(reaction.key, "👍");
+        assert_eq!(reaction.origin_server_ts, Some(UInt::try_from(1_604_001_234_567_u64).unwrap()));
+        assert_eq!(reaction.count, uint!(3));
+    }
+
+    #[test]
+    fn bundled_reaction_string_ts() {
+        let reaction = from_json_str::<BundledReaction>(
+            r#"{ "key": "👍", "origin_server_ts": "1604001234567", "count": 3 }"#,
+        )
+        .unwrap();
+        assert_eq!(reaction.origin_server_ts, Some(UInt::try_from(1_604_001_234_567_u64).unwrap()));
+
+        assert_eq!(
+            to_json_value(&reaction).unwrap(),
+            json!({ "key": "👍", "origin_server_ts": 1604001234567_u64, "count": 3 })
+        );
+    }
+
+    #[test]
+    fn bundled_reaction_without_ts() {
+        let reaction = from_json_str::<BundledReaction>(r#"{ "key": "👍", "count": 1 }"#).unwrap();
+        assert_eq!(reaction.origin_server_ts, None);
+    }
+
+    #[test]
+    fn annotation_chunk_from_reactions() {
+        let alice = user_id!("@alice:example.org");
+        let bob = user_id!("@bob:example.org");
+
+        let chunk = AnnotationChunk::from_reactions(
+            vec![
+                (alice.clone(), "👍".to_owned()),
+                (bob.clone(), "👍".to_owned()),
+                (alice.clone(), "🎉".to_owned()),
+            ],
+            ReactionKeyNormalization::Exact,
+        );
+
+        assert_eq!(chunk.chunk.len(), 2);
+        assert_eq!(chunk.chunk[0].key, "👍");
+        assert_eq!(chunk.chunk[0].count, uint!(2));
+        assert_eq!(chunk.chunk[0].users, vec![alice.clone(), bob]);
+        assert_eq!(chunk.chunk[1].key, "🎉");
+        assert_eq!(chunk.chunk[1].count, uint!(1));
+        assert_eq!(chunk.chunk[1].users, vec![alice]);
+    }
+
+    #[test]
+    fn annotation_chunk_from_reactions_normalized() {
+        let alice = user_id!("@alice:example.org");
+        let bob = user_id!("@bob:example.org");
+        let reactions = vec![(alice.clone(), "👍".to_owned()), (bob.clone(), "👍🏽".to_owned())];
+
+        let chunk = AnnotationChunk::from_reactions(
+            reactions.clone(),
+            ReactionKeyNormalization::IgnoreSkinTone,
+        );
+        assert_eq!(chunk.chunk.len(), 1);
+        assert_eq!(chunk.chunk[0].key, "👍");
+        assert_eq!(chunk.chunk[0].count, uint!(2));
+        assert_eq!(chunk.chunk[0].users, vec![alice, bob]);
+
+        let keys = reactions.iter().map(|(_, key)| key.as_str());
+        let counts = count_reactions(keys, ReactionKeyNormalization::IgnoreSkinTone);
+        assert_eq!(counts.get("👍"), Some(&2));
+    }
+
+    #[test]
+    fn bundled_reaction_to_reaction_content() {
+        let reaction = BundledReaction::new("👍".to_owned(), None, uint!(2));
+        let content = reaction.to_reaction_content(event_id!("$target:example.org"));
+
+        assert_eq!(
+            to_json_value(&content).unwrap(),
+            json!({
+                "m.relates_to": {
+                    "rel_type": "m.annotation",
+                    "event_id": "$target:example.org",
+                    "key": "👍",
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn bundled_reaction_eq_ignoring_ts() {
+        let reaction = BundledReaction::new("👍".to_owned(), Some(uint!(1_000)), uint!(2));
+
+        assert!(reaction.eq_ignoring_ts(&BundledReaction::new("👍".to_owned(), None, uint!(2))));
+        assert!(reaction.eq_ignoring_ts(&BundledReaction::new(
+            "👍".to_owned(),
+            Some(uint!(2_000)),
+            uint!(2)
+        )));
+        assert!(!reaction.eq_ignoring_ts(&BundledReaction::new(
+            "👍".to_owned(),
+            Some(uint!(1_000)),
+            uint!(3)
+        )));
+        assert!(!reaction.eq_ignoring_ts(&BundledReaction::new(
+            "👎".to_owned(),
+            Some(uint!(1_000)),
+            uint!(2)
+        )));
+
+        let mut with_users = reaction.clone();
+        with_users.users.push(user_id!("@alice:example.org"));
+        assert!(!reaction.eq_ignoring_ts(&with_users));
+    }
+
+    #[test]
+    fn bundled_replacement_with_ts() {
+        let replacement = from_json_value::<BundledReplacement>(json!({
+            "event_id": "$edit:example.org",
+            "origin_server_ts": 1_432_735_824_653_u64,
+            "sender": "@alice:example.org",
+        }))
+        .unwrap();
+
+        assert_eq!(replacement.event_id, event_id!("$edit:example.org"));
+        assert_eq!(
+            replacement.origin_server_ts,
+            Some(UInt::try_from(1_432_735_824_653_u64).unwrap())
+        );
+        assert_eq!(replacement.sender, Some(user_id!("@alice:example.org")));
+        assert_eq!(
+            replacement.timestamp(),
+            Some(UNIX_EPOCH + Duration::from_millis(1_432_735_824_653))
+        );
+    }
+
+    #[test]
+    fn bundled_replacement_without_ts() {
+        let replacement =
+            from_json_value::<BundledReplacement>(json!({ "event_id": "$edit:example.org" }))
+                .unwrap();
+
+        assert_eq!(replacement.timestamp(), None);
+        assert_eq!(
+            to_json_value(&replacement).unwrap(),
+            json!({ "event_id": "$edit:example.org" })
+        );
+    }
+
+    #[test]
+    fn merge_relations() {
+        let mut relations = from_json_value::<Relations>(json!({
+            "m.annotation": {
+                "chunk": [
+                    { "key": "👍", "origin_server_ts": 1_000, "count": 2 },
+                    { "key": "🎉", "count": 1 },
+                ],
+            },
+            "m.replace": { "event_id": "$edit1:example.org", "origin_server_ts": 1_000 },
+        }))
+        .unwrap();
+
+        relations.merge(
+            from_json_value::<Relations>(json!({
+                "m.annotation": {
+                    "chunk": [
+                        { "key": "👍", "origin_server_ts": 2_000, "count": 3 },
+                        { "key": "❤️", "count": 1 },
+                    ],
+                },
+                "m.reference": { "chunk": [{ "event_id": "$ref:example.org" }] },
+                "m.replace": { "event_id": "$edit2:example.org", "origin_server_ts": 2_000 },
+            }))
+            .unwrap(),
+        );
+
+        assert_eq!(
+            to_json_value(&relations).unwrap(),
+            json!({
+                "m.annotation": {
+                    "chunk": [
+                        { "key": "👍", "origin_server_ts": 2_000, "count": 5 },
+                        { "key": "🎉", "count": 1 },
+                        { "key": "❤️", "count": 1 },
+                    ],
+                },
+                "m.reference": { "chunk": [{ "event_id": "$ref:example.org" }] },
+                "m.replace": { "event_id": "$edit2:example.org", "origin_server_ts": 2_000 },
+            })
+        );
+    }
+
+    #[test]
+    fn annotation_chunk_apply_delta() {
+        let mut chunk =
+            AnnotationChunk::new(vec![BundledReaction::new("👍".to_owned(), None, uint!(1))]);
+
+        chunk.apply_delta("🎉", 1);
+        chunk.apply_delta("👍", 2);
+        assert_eq!(chunk.chunk.len(), 2);
+        assert_eq!(chunk.chunk[0].count, uint!(3));
+        assert_eq!(chunk.chunk[1].key, "🎉");
+        assert_eq!(chunk.chunk[1].count, uint!(1));
+
+        chunk.apply_delta("🎉", -1);
+        chunk.apply_delta("👍", -5);
+        chunk.apply_delta("😕", -1);
+        assert!(chunk.chunk.is_empty());
+    }
+
+    #[test]
+    fn merge_relations_saturates_counts() {
+        let chunk =
+            |count| AnnotationChunk::new(vec![BundledReaction::new("👍".to_owned(), None, count)]);
+        let mut relations = Relations { annotation: Some(chunk(UInt::MAX)), ..Relations::new() };
+
+        relations.merge(Relations { annotation: Some(chunk(uint!(2))), ..Relations::new() });
+
+        assert_eq!(relations.annotation.unwrap().chunk[0].count, UInt::MAX);
+    }
+
+    #[test]
+    fn merge_relations_keeps_newer_replacement() {
+        let mut relations = Relations {
+            replace: Some(BundledReplacement {
+                origin_server_ts: Some(uint!(2_000)),
+                ..BundledReplacement::new(event_id!("$edit2:example.org"))
+            }),
+            ..Relations::new()
+        };
+
+        relations.merge(Relations {
+            replace: Some(BundledReplacement {
+                origin_server_ts: Some(uint!(1_000)),
+                ..BundledReplacement::new(event_id!("$edit1:example.org"))
+            }),
+            ..Relations::new()
+        });
+
+        assert_eq!(relations.replace.unwrap().event_id, event_id!("$edit2:example.org"));
+        assert!(relations.annotation.is_none());
+    }
+}
@@ -52,12 +52,50 @@ pub struct ImageInfo {
     pub thumbnail_info: Option<Box<ThumbnailInfo>>,
 
     /// The URL to the thumbnail of the image. Only present if the thumbnail is unencrypted.
+    #[cfg_attr(feature = "compat", serde(alias = "thumb_url", alias = "thumbnailUrl"))]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub thumbnail_url: Option<String>,
 
     /// Information on the encrypted thumbnail image. Only present if the thumbnail is encrypted.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub thumbnail_file: Option<Box<EncryptedFile>>,
+
+    /// The [BlurHash](https://blurha.sh) for this image.
+    ///
+    /// This uses the unstable prefix in
+    /// [MSC2448](https://github.com/matrix-org/matrix-doc/pull/2448).
+    #[cfg(feature = "unstable-pre-spec")]
+    #[serde(rename = "xyz.amorgan.blurhash", skip_serializing_if = "Option::is_none")]
+    pub blurhash: Option<String>,
+}
+
+impl ImageInfo {
+    /// The length in bytes of the JSON serialization of this image metadata.
+    pub fn serialized_len(&self) -> usize {
+        serde_json::to_vec(self).expect("media info serialization can't fail").len()
+    }
+
+    /// Whether none of the fields of this image metadata are set.
+    pub fn is_empty(&self) -> bool {
+        self.height.is_none()
+            && self.width.is_none()
+            && self.mimetype.is_none()
+            && self.size.is_none()
+            && self.thumbnail_info.is_none()
+            && self.thumbnail_url.is_none()
+            && self.thumbnail_file.is_none()
+            && self.blurhash_is_none()
+    }
+
+    #[cfg(feature = "unstable-pre-spec")]
+    fn blurhash_is_none(&self) -> bool {
+        self.blurhash.is_none()
+    }
+
+    #[cfg(not(feature = "unstable-pre-spec"))]
+    fn blurhash_is_none(&self) -> bool {
+        true
+    }
 }
 
 /// Metadata about a thumbnail.
@@ -80,6 +118,99 @@ pub struct ThumbnailInfo {
     pub size: Option<UInt>,
 }
 
+impl MediaInfo for ThumbnailInfo {
+    fn mimetype(&self) -> Option<&str> {
+        self.mimetype.as_deref()
+    }
+
+    fn size(&self) -> Option<UInt> {
+        self.size
+    }
+}
+
+impl MediaInfo for ImageInfo {
+    fn mimetype(&self) -> Option<&str> {
+        self.mimetype.as_deref()
+    }
+
+    fn size(&self) -> Option<UInt> {
+        self.size
+    }
+}
+
+/// Common functionality of the metadata of media files.
+pub trait MediaInfo {
+    /// The MIME type of the media, if any.
+    fn mimetype(&self) -> Option<&str>;
+
+    /// The size of the media in bytes, if any.
+    fn size(&self) -> Option<UInt>;
+
+    /// The size of the media in a human-readable form, e.g. `"1.2 MB"`.
+    ///
+    /// This uses decimal units, i.e. one kilobyte is 1000 bytes. Returns `None` if the size is
+    /// missing.
+    fn human_size(&self) -> Option<String> {
+        const UNITS: &[&str] = &["kB", "MB", "GB", "TB"];
+
+        let bytes = u64::from(self.size()?);
+        if bytes < 1000 {
+            return Some(format!("{} B", bytes));
+        }
+
+        let mut size = bytes as f64 / 1000.0;
+        let mut unit = 0;
+        // Switch to the next unit before rounding would show 1000.0 of the current one.
+        while size >= 999.95 && unit < UNITS.len() - 1 {
+            size /= 1000.0;
+            unit += 1;
+        }
+
+        Some(format!("{:.1} {}", size, UNITS[unit]))
+    }
+
+    /// The category of the media, derived from the top-level type of its MIME type.
+    ///
+    /// Returns `MediaCategory::Other` if the MIME type is missing or unknown.
+    fn category(&self) -> MediaCategory {
+        let top_level = match self.mimetype() {
+            Some(mimetype) => mimetype.split('/').next().unwrap_or_default().trim(),
+            None => return MediaCategory::Other,
+        };
+
+        match top_level.to_ascii_lowercase().as_str() {
+            "image" => MediaCategory::Image,
+            "audio" => MediaCategory::Audio,
+            "video" => MediaCategory::Video,
+            "application" => MediaCategory::Application,
+            "text" => MediaCategory::Text,
+            _ => MediaCategory::Other,
+        }
+    }
+}
+
+/// The category of a media file, as returned by [`MediaInfo::category`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MediaCategory {
+    /// An image, e.g. `image/png`.
+    Image,
+
+    /// An audio file, e.g. `audio/ogg`.
+    Audio,
+
+    /// A video, e.g. `video/mp4`.
+    Video,
+
+    /// An application-specific file, e.g. `application/pdf`.
+    Application,
+
+    /// A text file, e.g. `text/plain`.
+    Text,
+
+    /// Any other or a missing MIME type.
+    Other,
+}
+
 /// A file sent to a room with end-to-end encryption enabled.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct EncryptedFile {
@@ -1,24 +1,50 @@
 //! Types for the *m.room.message* event.
 
+#[cfg(feature = "unstable-pre-spec")]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{
+    collections::BTreeMap,
+    convert::{Infallible, TryFrom},
+    error::Error,
+    fmt,
+    str::FromStr,
+};
+
 use js_int::UInt;
-use ruma_common::StringEnum;
+#[cfg(feature = "regex")]
+use regex::Regex;
+use ruma_common::{Raw, StringEnum};
 use ruma_events_macros::MessageEventContent;
-use serde::{Deserialize, Serialize};
-use serde_json::Value as JsonValue;
+#[cfg(feature = "unstable-pre-spec")]
+use ruma_identifiers::EventId;
+use ruma_identifiers::{RoomId, UserId};
+#[cfg(feature = "hashing")]
+use ruma_serde::CanonicalJsonValue;
+use ruma_serde::{to_canonical_json_string, to_canonical_value, CanonicalJsonError};
+use serde::{de::IgnoredAny, Deserialize, Serialize};
+use serde_json::{value::RawValue as RawJsonValue, Value as JsonValue};
+#[cfg(feature = "unicode")]
+use unicode_segmentation::UnicodeSegmentation;
 
 use super::{
     relationships::{RelatesToJsonRepr, RelationJsonRepr},
-    EncryptedFile, ImageInfo, ThumbnailInfo,
+    EncryptedFile, ImageInfo, MediaInfo, ThumbnailInfo,
 };
 
 pub use super::relationships::{Annotation, InReplyTo};
 
 #[cfg(feature = "unstable-pre-spec")]
-pub use super::relationships::{Reference, Replacement};
+pub use super::relationships::{Reference, Replacement, Thread};
 
+mod content_serde;
 pub mod feedback;
+#[cfg(feature = "html")]
+mod html;
 
-use crate::MessageEvent as OuterMessageEvent;
+#[cfg(feature = "html")]
+pub use html::SanitizerConfig;
+
+use crate::{InvalidInput, MessageEvent as OuterMessageEvent};
 
 /// This event is used when sending messages in a room.
 ///
@@ -70,6 +96,17 @@ pub enum MessageEventContent {
 
 /// Enum modeling the different ways relationships can be expressed in a
 /// `m.relates_to` field of an m.room.message event.
+///
+/// If the `m.relates_to` object matches more than one kind of relation, it is deserialized with
+/// the following precedence:
+///
+/// 1. If it has a `rel_type` that is known, it is parsed as that relation. An `m.in_reply_to` next
+///    to it is ignored, unless it is part of the relation like for threads.
+/// 2. Otherwise, if it has an `m.in_reply_to`, it is parsed as a `Reply`. The other keys, including
+///    an unknown `rel_type`, are kept in its `custom` field.
+/// 3. Otherwise, it is kept as `Custom`.
+///
+/// Content with more than one `m.relates_to` key fails to deserialize.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(from = "RelatesToJsonRepr", into = "RelatesToJsonRepr")]
 pub enum Relation {
@@ -89,12 +126,87 @@ pub enum Relation {
     Reply {
         /// Information about another message being replied to.
         in_reply_to: InReplyTo,
+
+        /// Unknown keys next to `m.in_reply_to`, preserved when re-serializing.
+        custom: BTreeMap<String, JsonValue>,
     },
 
+    /// An event that belongs to a thread.
+    #[cfg(feature = "unstable-pre-spec")]
+    Thread(Thread),
+
     /// Custom, unsupported relation.
     Custom(JsonValue),
 }
 
+impl Relation {
+    /// Creates a reference to the event with the given ID.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn reference(event_id: EventId) -> Self {
+        Self::Reference(Reference { event_id })
+    }
+
+    /// Creates a custom relation from the given `m.relates_to` JSON.
+    ///
+    /// The JSON is serialized verbatim, including nested objects. Note that when deserializing it
+    /// again, it is parsed as a known relation or a reply if it matches one, see the precedence
+    /// rules above.
+    pub fn custom(json: JsonValue) -> Self {
+        Self::Custom(json)
+    }
+
+    /// Returns the annotation if this is an annotation relation.
+    pub fn as_annotation(&self) -> Option<&Annotation> {
+        match self {
+            Self::Annotation(annotation) => Some(annotation),
+            _ => None,
+        }
+    }
+
+    /// Returns the reply to the latest event of the thread if this is a thread relation whose
+    /// `m.in_reply_to` is only a fallback for clients that don't support threads.
+    ///
+    /// A thread relation that is a genuine reply within the thread returns `None`.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn thread_fallback_reply(&self) -> Option<&InReplyTo> {
+        match self {
+            Self::Thread(Thread { in_reply_to, is_falling_back: true, .. }) => in_reply_to.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Whether this relation and `other` are of the same kind and relate to the same event.
+    ///
+    /// Annotations must also have the same key. Custom relations are compared by their `rel_type`
+    /// and `event_id` keys only, other keys are ignored; custom relations with neither are never
+    /// the same.
+    pub fn same_target_and_kind(&self, other: &Relation) -> bool {
+        match (self, other) {
+            (Self::Annotation(a), Self::Annotation(b)) => {
+                a.event_id == b.event_id && a.key == b.key
+            }
+            #[cfg(feature = "unstable-pre-spec")]
+            (Self::Reference(a), Self::Reference(b)) => a.event_id == b.event_id,
+            #[cfg(feature = "unstable-pre-spec")]
+            (Self::Replacement(a), Self::Replacement(b)) => a.event_id == b.event_id,
+            #[cfg(feature = "unstable-pre-spec")]
+            (Self::Thread(a), Self::Thread(b)) => a.event_id == b.event_id,
+            (Self::Reply { in_reply_to: a, .. }, Self::Reply { in_reply_to: b, .. }) => {
+                a.event_id == b.event_id
+            }
+            (Self::Custom(a), Self::Custom(b)) => {
+                let rel_type = (a.get("rel_type"), b.get("rel_type"));
+                let event_id = (a.get("event_id"), b.get("event_id"));
+
+                (rel_type.0.is_some() || event_id.0.is_some())
+                    && rel_type.0 == rel_type.1
+                    && event_id.0 == event_id.1
+            }
+            _ => false,
+        }
+    }
+}
+
 impl From<Relation> for RelatesToJsonRepr {
     fn from(value: Relation) -> Self {
         match value {
@@ -105,7 +217,11 @@ impl From<Relation> for RelatesToJsonRepr {
             Relation::Replacement(r) => {
                 RelatesToJsonRepr::Relation(RelationJsonRepr::Replacement(r))
             }
-            Relation::Reply { in_reply_to } => RelatesToJsonRepr::Reply { in_reply_to },
+            #[cfg(feature = "unstable-pre-spec")]
+            Relation::Thread(t) => RelatesToJsonRepr::Relation(RelationJsonRepr::Thread(t)),
+            Relation::Reply { in_reply_to, custom } => {
+                RelatesToJsonRepr::Reply { in_reply_to, custom }
+            }
             Relation::Custom(c) => RelatesToJsonRepr::Custom(c),
         }
     }
@@ -120,8 +236,10 @@ impl From<RelatesToJsonRepr> for Relation {
                 RelationJsonRepr::Reference(r) => Self::Reference(r),
                 #[cfg(feature = "unstable-pre-spec")]
                 RelationJsonRepr::Replacement(r) => Self::Replacement(r),
+                #[cfg(feature = "unstable-pre-spec")]
+                RelationJsonRepr::Thread(t) => Self::Thread(t),
             },
-            RelatesToJsonRepr::Reply { in_reply_to } => Self::Reply { in_reply_to },
+            RelatesToJsonRepr::Reply { in_reply_to, custom } => Self::Reply { in_reply_to, custom },
             RelatesToJsonRepr::Custom(v) => Self::Custom(v),
         }
     }
@@ -138,6 +256,16 @@ impl MessageEventContent {
         Self::Text(TextMessageEventContent::html(body, html_body))
     }
 
+    /// A convenience constructor to create a plain-text emote.
+    pub fn emote_plain(body: impl Into<String>) -> Self {
+        Self::Emote(EmoteMessageEventContent::plain(body))
+    }
+
+    /// A convenience constructor to create an html emote.
+    pub fn emote_html(body: impl Into<String>, html_body: impl Into<String>) -> Self {
+        Self::Emote(EmoteMessageEventContent::html(body, html_body))
+    }
+
     /// A convenience constructor to create an plain text notice.
     pub fn notice_plain(body: impl Into<String>) -> Self {
         Self::Notice(NoticeMessageEventContent::plain(body))
@@ -147,558 +275,5192 @@ impl MessageEventContent {
     pub fn notice_html(body: impl Into<String>, html_body: impl Into<String>) -> Self {
         Self::Notice(NoticeMessageEventContent::html(body, html_body))
     }
-}
 
-/// The payload for an audio message.
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct AudioMessageEventContent {
-    /// The textual representation of this message.
-    pub body: String,
+    /// A convenience constructor to create either a notice or a text message with the given
+    /// body and optional formatted body, e.g. for bots where this is a setting.
+    pub fn text_or_notice(
+        is_notice: bool,
+        body: impl Into<String>,
+        formatted: Option<FormattedBody>,
+    ) -> Self {
+        if is_notice {
+            Self::Notice(NoticeMessageEventContent {
+                formatted,
+                ..NoticeMessageEventContent::plain(body)
+            })
+        } else {
+            Self::Text(TextMessageEventContent {
+                formatted,
+                ..TextMessageEventContent::plain(body)
+            })
+        }
+    }
 
-    /// Metadata for the audio clip referred to in `url`.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub info: Option<Box<AudioInfo>>,
+    /// Deserializes message content from a JSON byte slice, without copying it into a `String`
+    /// first.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, serde_json::Error> {
+        serde_json::from_slice(bytes)
+    }
 
-    /// The URL to the audio clip. Required if the file is unencrypted. The URL (typically
-    /// [MXC URI](https://matrix.org/docs/spec/client_server/r0.6.1#mxc-uri)) to the audio clip.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub url: Option<String>,
+    /// Deserializes message content from JSON bytes that may not be valid UTF-8.
+    ///
+    /// Invalid UTF-8 sequences are replaced by the replacement character `U+FFFD` before parsing,
+    /// so **this can alter the content** of the message. It is meant for bridges that would rather
+    /// show a slightly corrupted message than drop it. Parsing can still fail if the bytes are not
+    /// valid JSON message content.
+    pub fn from_lossy_utf8(bytes: &[u8]) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(&String::from_utf8_lossy(bytes))
+    }
 
-    /// Required if the audio clip is encrypted. Information on the encrypted audio clip.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub file: Option<Box<EncryptedFile>>,
-}
+    /// Deserializes message content from a JSON value, trimming surrounding whitespace from the
+    /// `msgtype` first.
+    ///
+    /// Some buggy clients send a `msgtype` like `" m.text "`, which the regular `Deserialize`
+    /// implementation rejects. Use this to accept such messages anyway.
+    pub fn from_json_tolerant(mut json: JsonValue) -> Result<Self, serde_json::Error> {
+        if let Some(JsonValue::String(msgtype)) = json.get_mut("msgtype") {
+            let trimmed = msgtype.trim();
+            if trimmed.len() != msgtype.len() {
+                *msgtype = trimmed.to_owned();
+            }
+        }
 
-/// Metadata about an audio clip.
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct AudioInfo {
-    /// The duration of the audio in milliseconds.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub duration: Option<UInt>,
+        serde_json::from_value(json)
+    }
 
-    /// The mimetype of the audio, e.g. "audio/aac."
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub mimetype: Option<String>,
+    /// Whether the given raw message content has an `m.relates_to` field that is not `null`.
+    ///
+    /// This only looks for the field without deserializing the rest of the content, so it is
+    /// much cheaper than a full deserialization when scanning many events for relations. Invalid
+    /// JSON is treated as content without a relation.
+    pub fn raw_has_relation(raw: &RawJsonValue) -> bool {
+        #[derive(Deserialize)]
+        struct RelatesToOnly {
+            #[serde(rename = "m.relates_to")]
+            relates_to: Option<IgnoredAny>,
+        }
 
-    /// The size of the audio clip in bytes.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub size: Option<UInt>,
-}
+        serde_json::from_str::<RelatesToOnly>(raw.get())
+            .map(|content| content.relates_to.is_some())
+            .unwrap_or(false)
+    }
 
-/// The payload for an emote message.
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct EmoteMessageEventContent {
-    /// The emote action to perform.
-    pub body: String,
+    /// Converts the content of an [extensible event](https://github.com/matrix-org/matrix-doc/pull/1767)
+    /// into the closest legacy message content.
+    ///
+    /// `m.notice` and `m.emote` events are mapped to `Notice` and `Emote` respectively, all other
+    /// event types to `Text`. The text is taken from the `m.message` representations or the
+    /// `m.text` and `m.html` shorthands. Fails if the content has no `text/plain` representation,
+    /// even if it has an HTML one.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn from_extensible(
+        event_type: &str,
+        content: &RawJsonValue,
+    ) -> Result<Self, serde_json::Error> {
+        let content: ExtensibleTextContent = serde_json::from_str(content.get())?;
 
-    /// Formatted form of the message `body`.
-    #[serde(flatten)]
-    pub formatted: Option<FormattedBody>,
-}
+        let mut plain = content.text;
+        let mut html = content.html;
+        for representation in content.message {
+            match representation.mimetype.as_str() {
+                "text/plain" if plain.is_none() => plain = Some(representation.body),
+                "text/html" if html.is_none() => html = Some(representation.body),
+                _ => {}
+            }
+        }
 
-/// The payload for a file message.
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct FileMessageEventContent {
-    /// A human-readable description of the file. This is recommended to be the filename of the
-    /// original upload.
-    pub body: String,
+        // The plain body is the fallback for clients that don't render HTML, so the markup can't
+        // be used in its place.
+        let body =
+            plain.ok_or_else(|| serde::de::Error::custom("missing text/plain representation"))?;
+        let formatted = html.map(FormattedBody::html);
 
-    /// The original filename of the uploaded file.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub filename: Option<String>,
+        Ok(match event_type {
+            "m.emote" => Self::Emote(EmoteMessageEventContent { body, formatted }),
+            "m.notice" => Self::Notice(NoticeMessageEventContent {
+                formatted,
+                ..NoticeMessageEventContent::plain(body)
+            }),
+            _ => Self::Text(TextMessageEventContent {
+                formatted,
+                ..TextMessageEventContent::plain(body)
+            }),
+        })
+    }
 
-    /// Metadata about the file referred to in `url`.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub info: Option<Box<FileInfo>>,
+    /// Computes a SHA-256 hash of the canonical JSON of this content.
+    ///
+    /// Two contents with the same JSON representation have the same hash, regardless of key order.
+    /// Fails if the content can't be represented as canonical JSON, e.g. because a custom relation
+    /// contains a float.
+    #[cfg(feature = "hashing")]
+    pub fn content_hash(&self) -> Result<[u8; 32], CanonicalJsonError> {
+        Ok(Self::hash_canonical_value(to_canonical_value(self)?))
+    }
 
-    /// The URL to the file. Required if the file is unencrypted. The URL (typically
-    /// [MXC URI](https://matrix.org/docs/spec/client_server/r0.6.1#mxc-uri)) to the file.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub url: Option<String>,
+    /// Like [`content_hash`](#method.content_hash), but ignores the `m.relates_to` field.
+    ///
+    /// This allows detecting identical messages that were sent as replies to different events.
+    /// Note that reply fallbacks in the body are still part of the hash.
+    #[cfg(feature = "hashing")]
+    pub fn content_hash_without_relation(&self) -> Result<[u8; 32], CanonicalJsonError> {
+        let mut value = to_canonical_value(self)?;
+        if let CanonicalJsonValue::Object(object) = &mut value {
+            object.remove("m.relates_to");
+        }
 
-    /// Required if file is encrypted. Information on the encrypted file.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub file: Option<Box<EncryptedFile>>,
-}
+        Ok(Self::hash_canonical_value(value))
+    }
 
-/// Metadata about a file.
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct FileInfo {
-    /// The mimetype of the file, e.g. "application/msword."
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub mimetype: Option<String>,
+    #[cfg(feature = "hashing")]
+    fn hash_canonical_value(value: CanonicalJsonValue) -> [u8; 32] {
+        use sha2::{Digest, Sha256};
 
-    /// The size of the file in bytes.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub size: Option<UInt>,
+        let json = serde_json::to_vec(&value).expect("canonical JSON serialization can't fail");
+        let mut hash = [0; 32];
+        hash.copy_from_slice(&Sha256::digest(&json));
+        hash
+    }
 
-    /// Metadata about the image referred to in `thumbnail_url`.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub thumbnail_info: Option<Box<ThumbnailInfo>>,
+    /// The body of this message.
+    pub fn body(&self) -> &str {
+        match self {
+            Self::Audio(content) => &content.body,
+            Self::Emote(content) => &content.body,
+            Self::File(content) => &content.body,
+            Self::Image(content) => &content.body,
+            Self::Location(content) => &content.body,
+            Self::Notice(content) => &content.body,
+            Self::ServerNotice(content) => &content.body,
+            Self::Text(content) => &content.body,
+            Self::Video(content) => &content.body,
+        }
+    }
 
-    /// The URL to the thumbnail of the file. Only present if the thumbnail is unencrypted.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub thumbnail_url: Option<String>,
+    /// Applies `f` to the body of this message, leaving all other fields unchanged.
+    pub fn map_body(mut self, f: impl FnOnce(&str) -> String) -> Self {
+        let body = match &mut self {
+            Self::Audio(content) => &mut content.body,
+            Self::Emote(content) => &mut content.body,
+            Self::File(content) => &mut content.body,
+            Self::Image(content) => &mut content.body,
+            Self::Location(content) => &mut content.body,
+            Self::Notice(content) => &mut content.body,
+            Self::ServerNotice(content) => &mut content.body,
+            Self::Text(content) => &mut content.body,
+            Self::Video(content) => &mut content.body,
+        };
+        *body = f(body.as_str());
 
-    /// Information on the encrypted thumbnail file. Only present if the thumbnail is encrypted.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub thumbnail_file: Option<Box<EncryptedFile>>,
-}
+        self
+    }
 
-/// The payload for an image message.
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct ImageMessageEventContent {
-    /// A textual representation of the image. This could be the alt text of the image, the filename
-    /// of the image, or some kind of content description for accessibility e.g. "image attachment."
-    pub body: String,
+    /// Estimates how many lines the body of this message takes up when rendered at the given
+    /// width in characters.
+    ///
+    /// Every line of the body without the reply fallback counts as at least one line, and lines
+    /// longer than `width_chars` are assumed to wrap. This is only a heuristic that ignores
+    /// formatting, word boundaries and the actual width of characters.
+    pub fn estimated_line_count(&self, width_chars: usize) -> usize {
+        let width = width_chars.max(1);
+        let lines: usize = body_without_reply_fallback(self.body(), self.relates_to())
+            .lines()
+            .map(|line| {
+                let chars = line.chars().count();
+                if chars == 0 {
+                    1
+                } else {
+                    (chars - 1) / width + 1
+                }
+            })
+            .sum();
 
-    /// Metadata about the image referred to in `url`.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub info: Option<Box<ImageInfo>>,
+        lines.max(1)
+    }
 
-    /// The URL to the image. Required if the file is unencrypted. The URL (typically
-    /// [MXC URI](https://matrix.org/docs/spec/client_server/r0.6.1#mxc-uri)) to the image.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub url: Option<String>,
+    /// Returns a copy of this message with all matches of the given patterns in the body and
+    /// formatted body replaced by `[redacted]`.
+    ///
+    /// This is meant to scrub secrets like access tokens in URLs from messages before logging
+    /// them. The new content of edits is redacted as well.
+    #[cfg(feature = "regex")]
+    pub fn redact_sensitive(&self, patterns: &[Regex]) -> MessageEventContent {
+        let redact = |text: &str| {
+            patterns.iter().fold(text.to_owned(), |text, pattern| {
+                pattern.replace_all(&text, "[redacted]").into_owned()
+            })
+        };
 
-    /// Required if image is encrypted. Information on the encrypted image.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub file: Option<Box<EncryptedFile>>,
-}
+        let mut content = self.clone().map_body(redact);
+        match &mut content {
+            Self::Emote(EmoteMessageEventContent { formatted: Some(formatted), .. })
+            | Self::Notice(NoticeMessageEventContent { formatted: Some(formatted), .. })
+            | Self::Text(TextMessageEventContent { formatted: Some(formatted), .. }) => {
+                formatted.body = redact(&formatted.body);
+            }
+            _ => {}
+        }
 
-/// The payload for a location message.
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct LocationMessageEventContent {
-    /// A description of the location e.g. "Big Ben, London, UK,"or some kind of content description
-    /// for accessibility, e.g. "location attachment."
-    pub body: String,
+        #[cfg(feature = "unstable-pre-spec")]
+        match &mut content {
+            Self::Notice(NoticeMessageEventContent { new_content: Some(new_content), .. })
+            | Self::Text(TextMessageEventContent { new_content: Some(new_content), .. }) => {
+                **new_content = new_content.redact_sensitive(patterns);
+            }
+            _ => {}
+        }
 
-    /// A geo URI representing the location.
-    pub geo_uri: String,
+        content
+    }
 
-    /// Info about the location being represented.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub info: Option<Box<LocationInfo>>,
-}
+    /// Returns a displayable text for the given message content, even if it is malformed.
+    ///
+    /// For valid content, this is the body without the reply fallback. Otherwise, this is the
+    /// `body` key if it is a string, e.g. for content without a `msgtype`, or a placeholder if
+    /// there is no usable body at all.
+    pub fn best_effort_text(raw: &Raw<MessageEventContent>) -> String {
+        if let Ok(content) = raw.deserialize() {
+            return body_without_reply_fallback(content.body(), content.relates_to()).to_owned();
+        }
 
-/// Thumbnail info associated with a location.
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct LocationInfo {
-    /// Metadata about the image referred to in `thumbnail_url` or `thumbnail_file`.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub thumbnail_info: Option<Box<ThumbnailInfo>>,
+        #[derive(Deserialize)]
+        struct BodyOnly {
+            body: String,
 
-    /// The URL to a thumbnail of the location being represented. Only present if the thumbnail is
-    /// unencrypted.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub thumbnail_url: Option<String>,
+            #[serde(rename = "m.relates_to")]
+            relates_to: Option<Relation>,
+        }
 
-    /// Information on an encrypted thumbnail of the location being represented. Only present if the
-    /// thumbnail is encrypted.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub thumbnail_file: Option<Box<EncryptedFile>>,
-}
+        match serde_json::from_str::<BodyOnly>(raw.json().get()) {
+            Ok(BodyOnly { body, relates_to }) => {
+                body_without_reply_fallback(&body, relates_to.as_ref()).to_owned()
+            }
+            Err(_) => "[Unsupported message]".to_owned(),
+        }
+    }
 
-/// The payload for a notice message.
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct NoticeMessageEventContent {
-    /// The notice text to send.
-    pub body: String,
+    /// The `msgtype` of this message, e.g. `m.text`.
+    pub fn msgtype(&self) -> &str {
+        match self {
+            Self::Audio(_) => "m.audio",
+            Self::Emote(_) => "m.emote",
+            Self::File(_) => "m.file",
+            Self::Image(_) => "m.image",
+            Self::Location(_) => "m.location",
+            Self::Notice(_) => "m.notice",
+            Self::ServerNotice(_) => "m.server_notice",
+            Self::Text(_) => "m.text",
+            Self::Video(_) => "m.video",
+        }
+    }
 
-    /// Formatted form of the message `body`.
-    #[serde(flatten)]
-    pub formatted: Option<FormattedBody>,
+    /// If this message is an edit, returns the ID of the replaced event and the `msgtype` of the
+    /// new content.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn as_edit(&self) -> Option<(&EventId, &str)> {
+        match self {
+            Self::Notice(NoticeMessageEventContent {
+                relates_to: Some(Relation::Replacement(replacement)),
+                new_content: Some(new_content),
+                ..
+            })
+            | Self::Text(TextMessageEventContent {
+                relates_to: Some(Relation::Replacement(replacement)),
+                new_content: Some(new_content),
+                ..
+            }) => Some((&replacement.event_id, new_content.msgtype())),
+            _ => None,
+        }
+    }
 
-    /// Information about related messages for
-    /// [rich replies](https://matrix.org/docs/spec/client_server/r0.6.1#rich-replies).
-    #[serde(rename = "m.relates_to", skip_serializing_if = "Option::is_none")]
-    pub relates_to: Option<Relation>,
-}
+    /// Returns the content that clients which don't support edits or rich replies should display.
+    ///
+    /// For an edit, this is its `m.new_content`, which has neither the `* ` prefix of the
+    /// fallback body nor the relation to the edited event. Otherwise, this is a copy of this
+    /// message with the reply fallbacks of its plain and formatted bodies stripped.
+    pub fn displayed_content(&self) -> MessageEventContent {
+        #[cfg(feature = "unstable-pre-spec")]
+        match self {
+            Self::Notice(NoticeMessageEventContent {
+                relates_to: Some(Relation::Replacement(_)),
+                new_content: Some(new_content),
+                ..
+            })
+            | Self::Text(TextMessageEventContent {
+                relates_to: Some(Relation::Replacement(_)),
+                new_content: Some(new_content),
+                ..
+            }) => return new_content.displayed_content(),
+            _ => {}
+        }
 
-impl NoticeMessageEventContent {
-    /// A convenience constructor to create a plain text notice.
-    pub fn plain(body: impl Into<String>) -> Self {
-        Self { body: body.into(), formatted: None, relates_to: None }
+        let relates_to = self.relates_to();
+        let mut content = self.clone();
+        match &mut content {
+            Self::Emote(EmoteMessageEventContent { body, formatted, .. })
+            | Self::Notice(NoticeMessageEventContent { body, formatted, .. })
+            | Self::Text(TextMessageEventContent { body, formatted, .. }) => {
+                *body = body_without_reply_fallback(body, relates_to).to_owned();
+                if let Some(formatted) = formatted {
+                    formatted.body = strip_html_reply_fallback(&formatted.body).to_owned();
+                }
+            }
+            _ => {}
+        }
+
+        content
     }
 
-    /// A convenience constructor to create an html notice.
-    pub fn html(body: impl Into<String>, html_body: impl Into<String>) -> Self {
-        Self {
-            body: body.into(),
-            formatted: Some(FormattedBody::html(html_body)),
-            relates_to: None,
+    /// Whether this is a notice, either a regular `m.notice` or an `m.server_notice`.
+    pub fn is_any_notice(&self) -> bool {
+        matches!(self, Self::Notice(_) | Self::ServerNotice(_))
+    }
+
+    /// Whether this is a media message, i.e. an audio clip, file, image or video.
+    pub fn is_media(&self) -> bool {
+        matches!(self, Self::Audio(_) | Self::File(_) | Self::Image(_) | Self::Video(_))
+    }
+
+    /// Removes the `info` of media and location messages if none of its fields are set, so it
+    /// isn't serialized as an empty object.
+    pub fn prune(&mut self) {
+        match self {
+            Self::Audio(content) => prune_info(&mut content.info, AudioInfo::is_empty),
+            Self::File(content) => prune_info(&mut content.info, FileInfo::is_empty),
+            Self::Image(content) => prune_info(&mut content.info, ImageInfo::is_empty),
+            Self::Location(content) => prune_info(&mut content.info, LocationInfo::is_empty),
+            Self::Video(content) => prune_info(&mut content.info, VideoInfo::is_empty),
+            _ => {}
         }
     }
-}
 
-/// The payload for a server notice message.
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct ServerNoticeMessageEventContent {
-    /// A human-readable description of the notice.
-    pub body: String,
+    /// Serializes this content as
+    /// [canonical JSON](https://matrix.org/docs/spec/appendices#canonical-json), i.e. with
+    /// lexicographically sorted keys and without insignificant whitespace.
+    pub fn to_canonical_json(&self) -> Result<String, CanonicalJsonError> {
+        to_canonical_json_string(&to_canonical_value(self)?)
+    }
 
-    /// The type of notice being represented.
-    pub server_notice_type: ServerNoticeType,
+    /// Serializes this content as indented JSON, e.g. for debugging.
+    pub fn to_json_pretty(&self) -> String {
+        serde_json::to_string_pretty(self).expect("message content serialization can't fail")
+    }
 
-    /// A URI giving a contact method for the server administrator.
+    /// Returns a short, single-line preview of this message, e.g. for notifications.
     ///
-    /// Required if the notice type is `m.server_notice.usage_limit_reached`.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub admin_contact: Option<String>,
+    /// Reply fallbacks are stripped and whitespace is collapsed. Media and location messages
+    /// without a body get a placeholder such as "📷 Image". The result is at most `max_len`
+    /// characters long, ending in "…" if it was truncated.
+    pub fn preview(&self, max_len: usize) -> String {
+        let (body, placeholder) = match self {
+            Self::Audio(content) => (content.body.as_str(), "🎵 Audio"),
+            Self::Emote(content) => (content.body.as_str(), ""),
+            Self::File(content) => (content.body.as_str(), "📎 File"),
+            Self::Image(content) => (content.body.as_str(), "📷 Image"),
+            Self::Location(content) => (content.body.as_str(), "📍 Location"),
+            Self::Notice(content) => {
+                (body_without_reply_fallback(&content.body, content.relates_to.as_ref()), "")
+            }
+            Self::ServerNotice(content) => {
+                (body_without_reply_fallback(&content.body, content.relates_to.as_ref()), "")
+            }
+            Self::Text(content) => {
+                (body_without_reply_fallback(&content.body, content.relates_to.as_ref()), "")
+            }
+            Self::Video(content) => (content.body.as_str(), "🎥 Video"),
+        };
 
-    /// The kind of usage limit the server has exceeded.
+        let line = body.split_whitespace().collect::<Vec<_>>().join(" ");
+        let line = if line.is_empty() { placeholder.to_owned() } else { line };
+
+        if line.chars().count() <= max_len {
+            line
+        } else if max_len == 0 {
+            String::new()
+        } else {
+            let mut truncated: String = line.chars().take(max_len - 1).collect();
+            truncated.push('…');
+            truncated
+        }
+    }
+
+    /// Returns the relation of this message, if it has one.
     ///
-    /// Required if the notice type is `m.server_notice.usage_limit_reached`.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub limit_type: Option<LimitType>,
-}
+    /// Only text messages, notices and server notices can have a relation, this returns `None` for
+    /// all other message types.
+    pub fn relates_to(&self) -> Option<&Relation> {
+        match self {
+            Self::Notice(content) => content.relates_to.as_ref(),
+            Self::ServerNotice(content) => content.relates_to.as_ref(),
+            Self::Text(content) => content.relates_to.as_ref(),
+            _ => None,
+        }
+    }
 
-/// Types of server notices.
-#[derive(Clone, Debug, PartialEq, Eq, StringEnum)]
-pub enum ServerNoticeType {
-    /// The server has exceeded some limit which requires the server administrator to intervene.
-    #[ruma_enum(rename = "m.server_notice.usage_limit_reached")]
-    UsageLimitReached,
+    /// The ID of the root of the thread this message belongs to, if any.
+    ///
+    /// This only looks at the content of the message, so it can be used as a fallback to find
+    /// out which thread a read receipt belongs to.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn thread_root(&self) -> Option<&EventId> {
+        match self.relates_to() {
+            Some(Relation::Thread(thread)) => Some(&thread.event_id),
+            _ => None,
+        }
+    }
 
-    #[doc(hidden)]
-    _Custom(String),
+    /// Sets or clears the relation of this message.
+    ///
+    /// Only text messages, notices and server notices can have a relation, for all other message
+    /// types this does nothing.
+    pub fn with_relation(mut self, relation: Option<Relation>) -> Self {
+        match &mut self {
+            Self::Notice(content) => content.relates_to = relation,
+            Self::ServerNotice(content) => content.relates_to = relation,
+            Self::Text(content) => content.relates_to = relation,
+            _ => {}
+        }
+
+        self
+    }
+
+    /// Returns a copy of this message with the given relation, or without a relation if it is
+    /// `None`.
+    ///
+    /// See [`with_relation`](#method.with_relation) for the message types that support relations.
+    pub fn clone_with_relation(&self, relation: Option<Relation>) -> Self {
+        self.clone().with_relation(relation)
+    }
+
+    /// Returns all plain URLs of media in this message, e.g. to prefetch them.
+    ///
+    /// This includes the URL of the media itself and of its thumbnail. Encrypted media and
+    /// thumbnails are not included since they can't be fetched by URL alone.
+    pub fn media_urls(&self) -> Vec<&str> {
+        let (url, thumbnail_url) = match self {
+            Self::Audio(content) => (content.url.as_deref(), None),
+            Self::File(content) => (
+                content.url.as_deref(),
+                content.info.as_ref().and_then(|info| info.thumbnail_url.as_deref()),
+            ),
+            Self::Image(content) => (
+                content.url.as_deref(),
+                content.info.as_ref().and_then(|info| info.thumbnail_url.as_deref()),
+            ),
+            Self::Location(content) => {
+                (None, content.info.as_ref().and_then(|info| info.thumbnail_url.as_deref()))
+            }
+            Self::Video(content) => (
+                content.url.as_deref(),
+                content.info.as_ref().and_then(|info| info.thumbnail_url.as_deref()),
+            ),
+            _ => (None, None),
+        };
+
+        url.into_iter().chain(thumbnail_url).collect()
+    }
+
+    /// Returns a description of this message for screen readers.
+    ///
+    /// Media, location and server notice messages are described by their type and body, with the
+    /// duration of audio and video clips if it is known, e.g. "Audio: memo.ogg, 12 seconds". For
+    /// other messages, this is the body without the reply fallback.
+    pub fn accessibility_label(&self) -> String {
+        let duration_suffix = |info: Option<UInt>| match info.map(u64::from) {
+            Some(ms) if ms / 1000 == 1 => ", 1 second".to_owned(),
+            Some(ms) => format!(", {} seconds", ms / 1000),
+            None => String::new(),
+        };
+
+        match self {
+            Self::Audio(content) => format!(
+                "Audio: {}{}",
+                content.body,
+                duration_suffix(content.info.as_ref().and_then(|info| info.duration))
+            ),
+            Self::File(content) => {
+                format!("File: {}", content.filename.as_ref().unwrap_or(&content.body))
+            }
+            Self::Image(content) => format!("Image: {}", content.body),
+            Self::Location(content) => format!("Location: {}", content.body),
+            Self::ServerNotice(content) => format!("Server notice: {}", content.body),
+            Self::Video(content) => format!(
+                "Video: {}{}",
+                content.body,
+                duration_suffix(content.info.as_ref().and_then(|info| info.duration))
+            ),
+            Self::Emote(_) | Self::Notice(_) | Self::Text(_) => {
+                body_without_reply_fallback(self.body(), self.relates_to()).to_owned()
+            }
+        }
+    }
+
+    /// Returns the text of this message that should be indexed for full-text search.
+    ///
+    /// This is the body with any reply fallback stripped. For file messages, the filename is
+    /// appended if it differs from the body. For location messages, the body is the description
+    /// of the location.
+    pub fn search_text(&self) -> String {
+        let body = body_without_reply_fallback(self.body(), self.relates_to());
+
+        match self {
+            Self::File(FileMessageEventContent { filename: Some(filename), .. })
+                if filename != body =>
+            {
+                format!("{}\n{}", body, filename)
+            }
+            _ => body.to_owned(),
+        }
+    }
+
+    /// Walks through the strings of this message, calling the matching method of `visitor` for
+    /// each of them.
+    ///
+    /// This allows analyzing all kinds of messages without matching on their type, e.g. to
+    /// collect all the MXC URIs they refer to.
+    pub fn visit(&self, visitor: &mut impl MessageVisitor) {
+        visitor.on_body(self.body());
+
+        match self {
+            Self::Audio(content) => {
+                #[cfg(feature = "unstable-pre-spec")]
+                visit_opt(&content.filename, |filename| visitor.on_filename(filename));
+                visit_media(visitor, &content.url, &content.file);
+                if let Some(info) = &content.info {
+                    visit_opt(&info.mimetype, |mimetype| visitor.on_mimetype(mimetype));
+                }
+            }
+            Self::Emote(EmoteMessageEventContent { formatted, .. })
+            | Self::Notice(NoticeMessageEventContent { formatted, .. })
+            | Self::Text(TextMessageEventContent { formatted, .. }) => {
+                if let Some(formatted) = formatted {
+                    visitor.on_formatted_body(formatted);
+                }
+            }
+            Self::File(content) => {
+                visit_opt(&content.filename, |filename| visitor.on_filename(filename));
+                visit_media(visitor, &content.url, &content.file);
+                if let Some(info) = &content.info {
+                    visit_opt(&info.mimetype, |mimetype| visitor.on_mimetype(mimetype));
+                    visit_thumbnail(
+                        visitor,
+                        &info.thumbnail_url,
+                        &info.thumbnail_file,
+                        &info.thumbnail_info,
+                    );
+                }
+            }
+            Self::Image(content) => {
+                #[cfg(feature = "unstable-pre-spec")]
+                visit_opt(&content.filename, |filename| visitor.on_filename(filename));
+                visit_media(visitor, &content.url, &content.file);
+                if let Some(info) = &content.info {
+                    visit_opt(&info.mimetype, |mimetype| visitor.on_mimetype(mimetype));
+                    visit_thumbnail(
+                        visitor,
+                        &info.thumbnail_url,
+                        &info.thumbnail_file,
+                        &info.thumbnail_info,
+                    );
+                }
+            }
+            Self::Location(content) => {
+                visitor.on_geo_uri(&content.geo_uri);
+                if let Some(info) = &content.info {
+                    visit_thumbnail(
+                        visitor,
+                        &info.thumbnail_url,
+                        &info.thumbnail_file,
+                        &info.thumbnail_info,
+                    );
+                }
+            }
+            Self::ServerNotice(content) => {
+                visit_opt(&content.admin_contact, |admin_contact| visitor.on_url(admin_contact));
+            }
+            Self::Video(content) => {
+                #[cfg(feature = "unstable-pre-spec")]
+                visit_opt(&content.filename, |filename| visitor.on_filename(filename));
+                visit_media(visitor, &content.url, &content.file);
+                if let Some(info) = &content.info {
+                    visit_opt(&info.mimetype, |mimetype| visitor.on_mimetype(mimetype));
+                    visit_thumbnail(
+                        visitor,
+                        &info.thumbnail_url,
+                        &info.thumbnail_file,
+                        &info.thumbnail_info,
+                    );
+                }
+            }
+        }
+    }
 }
 
-/// Types of usage limits.
-#[derive(Clone, Debug, PartialEq, Eq, StringEnum)]
-#[ruma_enum(rename_all = "snake_case")]
-pub enum LimitType {
-    /// The server's number of active users in the last 30 days has exceeded the maximum.
+/// A read-only visitor over the strings of a [`MessageEventContent`].
+///
+/// All methods do nothing by default, so implementors only need to override the ones they are
+/// interested in. See [`MessageEventContent::visit`].
+pub trait MessageVisitor {
+    /// Called with the `body` of the message.
+    fn on_body(&mut self, _body: &str) {}
+
+    /// Called with the formatted body of a text, notice or emote message, if it has one.
+    fn on_formatted_body(&mut self, _formatted: &FormattedBody) {}
+
+    /// Called with every URL in the message.
     ///
-    /// New connections are being refused by the server. What defines "active" is left as an
-    /// implementation detail, however servers are encouraged to treat syncing users as "active".
-    MonthlyActiveUser,
+    /// This includes the URLs of the media and its thumbnail, whether they are encrypted or not,
+    /// and the admin contact of server notices.
+    fn on_url(&mut self, _url: &str) {}
 
-    #[doc(hidden)]
-    _Custom(String),
+    /// Called with the mimetypes of the media and its thumbnail.
+    fn on_mimetype(&mut self, _mimetype: &str) {}
+
+    /// Called with the filename of the media, if it has one.
+    fn on_filename(&mut self, _filename: &str) {}
+
+    /// Called with the geo URI of a location message.
+    fn on_geo_uri(&mut self, _geo_uri: &str) {}
 }
 
-/// The format for the formatted representation of a message body.
+/// A `MessageEventContent` that can be used with formats that are not self-describing, like
+/// postcard or bincode.
 ///
-/// This type can hold an arbitrary string. To check for events that are not
-/// available as a documented variant here, use its string representation,
-/// obtained through `.as_str()`.
-#[derive(Clone, Debug, PartialEq, Eq, StringEnum)]
-pub enum MessageFormat {
-    /// HTML.
-    #[ruma_enum(rename = "org.matrix.custom.html")]
-    Html,
+/// `MessageEventContent` is internally tagged by `msgtype` and has flattened and optional fields,
+/// which these formats can't represent. This wrapper serializes the content as a JSON string
+/// instead, so it can be used e.g. when caching events in a compact binary form.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BinaryMessageEventContent(
+    /// The wrapped message content.
+    #[serde(with = "ruma_serde::json_string")]
+    pub MessageEventContent,
+);
 
-    #[doc(hidden)]
-    _Custom(String),
+impl From<MessageEventContent> for BinaryMessageEventContent {
+    fn from(content: MessageEventContent) -> Self {
+        Self(content)
+    }
 }
 
-impl MessageFormat {
-    /// Creates a string slice from this `MessageFormat`.
-    pub fn as_str(&self) -> &str {
-        self.as_ref()
+impl From<BinaryMessageEventContent> for MessageEventContent {
+    fn from(content: BinaryMessageEventContent) -> Self {
+        content.0
     }
 }
 
-/// Common message event content fields for message types that have separate plain-text and
-/// formatted representations.
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct FormattedBody {
-    /// The format used in the `formatted_body`.
-    pub format: MessageFormat,
+macro_rules! impl_from_payload {
+    ($($variant:ident($payload:ident) = $msgtype:literal),* $(,)?) => {
+        $(
+            impl From<$payload> for MessageEventContent {
+                fn from(content: $payload) -> Self {
+                    Self::$variant(content)
+                }
+            }
 
-    /// The formatted version of the `body`.
-    #[serde(rename = "formatted_body")]
+            impl TryFrom<MessageEventContent> for $payload {
+                type Error = WrongVariant;
+
+                fn try_from(content: MessageEventContent) -> Result<Self, Self::Error> {
+                    match content {
+                        MessageEventContent::$variant(content) => Ok(content),
+                        content => Err(WrongVariant { expected: $msgtype, content }),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_payload! {
+    Audio(AudioMessageEventContent) = "m.audio",
+    Emote(EmoteMessageEventContent) = "m.emote",
+    File(FileMessageEventContent) = "m.file",
+    Image(ImageMessageEventContent) = "m.image",
+    Location(LocationMessageEventContent) = "m.location",
+    Notice(NoticeMessageEventContent) = "m.notice",
+    ServerNotice(ServerNoticeMessageEventContent) = "m.server_notice",
+    Text(TextMessageEventContent) = "m.text",
+    Video(VideoMessageEventContent) = "m.video",
+}
+
+/// An error returned when converting a `MessageEventContent` into a payload of another type.
+///
+/// It contains the original content, so it isn't lost if the conversion fails.
+#[derive(Clone, Debug)]
+pub struct WrongVariant {
+    expected: &'static str,
+    content: MessageEventContent,
+}
+
+impl WrongVariant {
+    /// The `msgtype` of the payload that was expected.
+    pub fn expected(&self) -> &'static str {
+        self.expected
+    }
+
+    /// Returns the content that couldn't be converted.
+    pub fn into_content(self) -> MessageEventContent {
+        self.content
+    }
+}
+
+impl fmt::Display for WrongVariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected a message of type `{}`, found `{}`",
+            self.expected,
+            self.content.msgtype()
+        )
+    }
+}
+
+impl Error for WrongVariant {}
+
+/// The payload for an audio message.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AudioMessageEventContent {
+    /// The textual representation of this message.
     pub body: String,
+
+    /// The original filename of the uploaded audio clip, if it differs from `body`.
+    ///
+    /// If this is set, `body` can be used as a caption.
+    #[cfg(feature = "unstable-pre-spec")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+
+    /// Metadata for the audio clip referred to in `url`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub info: Option<Box<AudioInfo>>,
+
+    /// The URL to the audio clip. Required if the file is unencrypted. The URL (typically
+    /// [MXC URI](https://matrix.org/docs/spec/client_server/r0.6.1#mxc-uri)) to the audio clip.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+
+    /// Required if the audio clip is encrypted. Information on the encrypted audio clip.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<Box<EncryptedFile>>,
 }
 
-impl FormattedBody {
-    /// Creates a new HTML-formatted message body.
-    pub fn html(body: impl Into<String>) -> Self {
-        Self { format: MessageFormat::Html, body: body.into() }
+impl AudioMessageEventContent {
+    /// Creates a new message for an encrypted audio clip, without a `url`.
+    pub fn encrypted(
+        body: impl Into<String>,
+        file: EncryptedFile,
+        info: Option<AudioInfo>,
+    ) -> Self {
+        Self {
+            body: body.into(),
+            #[cfg(feature = "unstable-pre-spec")]
+            filename: None,
+            info: info.map(Box::new),
+            url: None,
+            file: Some(Box::new(file)),
+        }
+    }
+
+    /// Sets the metadata about the audio clip, replacing any existing metadata.
+    pub fn with_info(mut self, info: AudioInfo) -> Self {
+        self.info = Some(Box::new(info));
+        self
+    }
+
+    /// Creates a new message for the audio clip at the given URL, with a default body.
+    ///
+    /// The body is derived from the mimetype in `info`, e.g. `Audio.ogg` for `audio/ogg`, or
+    /// is just `Audio` if the mimetype is unknown.
+    pub fn with_default_body(url: impl Into<String>, info: Option<AudioInfo>) -> Self {
+        let body = default_media_body("Audio", info.as_ref().and_then(|i| i.mimetype.as_deref()));
+        Self {
+            body,
+            #[cfg(feature = "unstable-pre-spec")]
+            filename: None,
+            info: info.map(Box::new),
+            url: Some(url.into()),
+            file: None,
+        }
+    }
+
+    /// The filename to display for this audio clip.
+    ///
+    /// This is `filename` if it is set, `body` otherwise.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn display_filename(&self) -> &str {
+        self.filename.as_deref().unwrap_or(&self.body)
+    }
+
+    /// Creates a new message for the audio clip at the given URL with a caption.
+    ///
+    /// The caption is used as the `body` and the name of the audio clip is set as the `filename`.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn with_caption_and_filename(
+        url: impl Into<String>,
+        info: Option<AudioInfo>,
+        filename: impl Into<String>,
+        caption: impl Into<String>,
+    ) -> Self {
+        Self {
+            body: caption.into(),
+            filename: Some(filename.into()),
+            ..Self::with_default_body(url, info)
+        }
     }
 }
 
-/// The payload for a text message.
+/// Metadata about an audio clip.
 #[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct TextMessageEventContent {
-    /// The body of the message.
+pub struct AudioInfo {
+    /// The duration of the audio in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<UInt>,
+
+    /// The mimetype of the audio, e.g. "audio/aac."
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mimetype: Option<String>,
+
+    /// The size of the audio clip in bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<UInt>,
+}
+
+impl MediaInfo for AudioInfo {
+    fn mimetype(&self) -> Option<&str> {
+        self.mimetype.as_deref()
+    }
+
+    fn size(&self) -> Option<UInt> {
+        self.size
+    }
+}
+
+impl AudioInfo {
+    /// The length in bytes of the JSON serialization of this audio metadata.
+    pub fn serialized_len(&self) -> usize {
+        serde_json::to_vec(self).expect("media info serialization can't fail").len()
+    }
+
+    /// Whether none of the fields of this audio metadata are set.
+    pub fn is_empty(&self) -> bool {
+        self.duration.is_none() && self.mimetype.is_none() && self.size.is_none()
+    }
+
+    /// Whether the declared size or duration of the audio clip exceeds the given limits.
+    ///
+    /// Metadata that is not present is not considered to exceed any limit.
+    pub fn exceeds(&self, max_size: UInt, max_duration_ms: UInt) -> bool {
+        self.size.map_or(false, |size| size > max_size)
+            || self.duration.map_or(false, |duration| duration > max_duration_ms)
+    }
+}
+
+/// The payload for an emote message.
+#[derive(Clone, Debug, Deserialize)]
+pub struct EmoteMessageEventContent {
+    /// The emote action to perform.
     pub body: String,
 
     /// Formatted form of the message `body`.
     #[serde(flatten)]
     pub formatted: Option<FormattedBody>,
-
-    /// Information about related messages for
-    /// [rich replies](https://matrix.org/docs/spec/client_server/r0.6.1#rich-replies).
-    #[serde(rename = "m.relates_to", skip_serializing_if = "Option::is_none")]
-    pub relates_to: Option<Relation>,
 }
 
-impl TextMessageEventContent {
-    /// A convenience constructor to create a plain text message.
+impl EmoteMessageEventContent {
+    /// A convenience constructor to create a plain-text emote.
     pub fn plain(body: impl Into<String>) -> Self {
-        Self { body: body.into(), formatted: None, relates_to: None }
+        Self { body: body.into(), formatted: None }
+    }
+
+    /// A convenience constructor to create an html emote message.
+    pub fn html(body: impl Into<String>, html_body: impl Into<String>) -> Self {
+        Self { body: body.into(), formatted: Some(FormattedBody::html(html_body)) }
+    }
+}
+
+/// The payload for a file message.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FileMessageEventContent {
+    /// A human-readable description of the file. This is recommended to be the filename of the
+    /// original upload.
+    pub body: String,
+
+    /// The original filename of the uploaded file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+
+    /// Metadata about the file referred to in `url`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub info: Option<Box<FileInfo>>,
+
+    /// The URL to the file. Required if the file is unencrypted. The URL (typically
+    /// [MXC URI](https://matrix.org/docs/spec/client_server/r0.6.1#mxc-uri)) to the file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+
+    /// Required if file is encrypted. Information on the encrypted file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<Box<EncryptedFile>>,
+}
+
+impl FileMessageEventContent {
+    /// Creates a new message for an encrypted file, without a `url`.
+    pub fn encrypted(body: impl Into<String>, file: EncryptedFile, info: Option<FileInfo>) -> Self {
+        Self {
+            body: body.into(),
+            filename: None,
+            info: info.map(Box::new),
+            url: None,
+            file: Some(Box::new(file)),
+        }
+    }
+
+    /// Sets the metadata about the file, replacing any existing metadata.
+    pub fn with_info(mut self, info: FileInfo) -> Self {
+        self.info = Some(Box::new(info));
+        self
+    }
+
+    /// Creates a new message for the file at the given URL, with a default body.
+    ///
+    /// The body is the filename if there is one. Otherwise it is derived from the mimetype in
+    /// `info`, e.g. `File.pdf` for `application/pdf`, or is just `File` if the mimetype is
+    /// unknown.
+    pub fn with_default_body(
+        url: impl Into<String>,
+        filename: Option<String>,
+        info: Option<FileInfo>,
+    ) -> Self {
+        let body = filename.clone().unwrap_or_else(|| {
+            default_media_body("File", info.as_ref().and_then(|i| i.mimetype.as_deref()))
+        });
+        Self { body, filename, info: info.map(Box::new), url: Some(url.into()), file: None }
+    }
+
+    /// The filename to display for this file.
+    ///
+    /// This is `filename` if it is set, `body` otherwise.
+    pub fn display_filename(&self) -> &str {
+        self.filename.as_deref().unwrap_or(&self.body)
+    }
+
+    /// Creates a new message for the file at the given URL with a caption.
+    ///
+    /// The caption is used as the `body` and the name of the file is set as the `filename`.
+    pub fn with_caption_and_filename(
+        url: impl Into<String>,
+        info: Option<FileInfo>,
+        filename: impl Into<String>,
+        caption: impl Into<String>,
+    ) -> Self {
+        Self { body: caption.into(), ..Self::with_default_body(url, Some(filename.into()), info) }
+    }
+}
+
+/// Metadata about a file.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FileInfo {
+    /// The mimetype of the file, e.g. "application/msword."
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mimetype: Option<String>,
+
+    /// The size of the file in bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<UInt>,
+
+    /// Metadata about the image referred to in `thumbnail_url`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_info: Option<Box<ThumbnailInfo>>,
+
+    /// The URL to the thumbnail of the file. Only present if the thumbnail is unencrypted.
+    #[cfg_attr(feature = "compat", serde(alias = "thumb_url", alias = "thumbnailUrl"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_url: Option<String>,
+
+    /// Information on the encrypted thumbnail file. Only present if the thumbnail is encrypted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_file: Option<Box<EncryptedFile>>,
+}
+
+impl MediaInfo for FileInfo {
+    fn mimetype(&self) -> Option<&str> {
+        self.mimetype.as_deref()
+    }
+
+    fn size(&self) -> Option<UInt> {
+        self.size
+    }
+}
+
+impl FileInfo {
+    /// The length in bytes of the JSON serialization of this file metadata.
+    pub fn serialized_len(&self) -> usize {
+        serde_json::to_vec(self).expect("media info serialization can't fail").len()
+    }
+
+    /// Whether none of the fields of this file metadata are set.
+    pub fn is_empty(&self) -> bool {
+        self.mimetype.is_none()
+            && self.size.is_none()
+            && self.thumbnail_info.is_none()
+            && self.thumbnail_url.is_none()
+            && self.thumbnail_file.is_none()
+    }
+
+    /// Sets an encrypted thumbnail with the given metadata.
+    ///
+    /// Since a thumbnail is either encrypted or not, this clears `thumbnail_url`.
+    pub fn with_encrypted_thumbnail(mut self, file: EncryptedFile, info: ThumbnailInfo) -> Self {
+        self.thumbnail_file = Some(Box::new(file));
+        self.thumbnail_info = Some(Box::new(info));
+        self.thumbnail_url = None;
+        self
+    }
+
+    /// Whether the declared size of the file exceeds the given limit.
+    ///
+    /// A file without a declared size is not considered to exceed the limit.
+    pub fn exceeds(&self, max_size: UInt) -> bool {
+        self.size.map_or(false, |size| size > max_size)
+    }
+}
+
+/// The payload for an image message.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ImageMessageEventContent {
+    /// A textual representation of the image. This could be the alt text of the image, the filename
+    /// of the image, or some kind of content description for accessibility e.g. "image attachment."
+    pub body: String,
+
+    /// The original filename of the uploaded image, if it differs from `body`.
+    ///
+    /// If this is set, `body` can be used as a caption.
+    #[cfg(feature = "unstable-pre-spec")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+
+    /// Whether the image should be rendered like a sticker.
+    ///
+    /// This is a rendering hint for clients that use image messages for sticker-like content. It
+    /// is serialized under a vendor-prefixed key and only if it is `true`.
+    #[cfg(feature = "unstable-pre-spec")]
+    #[serde(
+        rename = "io.ruma.sticker_like",
+        default,
+        skip_serializing_if = "ruma_serde::is_default"
+    )]
+    pub sticker_like: bool,
+
+    /// Metadata about the image referred to in `url`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub info: Option<Box<ImageInfo>>,
+
+    /// The URL to the image. Required if the file is unencrypted. The URL (typically
+    /// [MXC URI](https://matrix.org/docs/spec/client_server/r0.6.1#mxc-uri)) to the image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+
+    /// Required if image is encrypted. Information on the encrypted image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<Box<EncryptedFile>>,
+}
+
+impl ImageMessageEventContent {
+    /// Creates a new message for an encrypted image, without a `url`.
+    pub fn encrypted(
+        body: impl Into<String>,
+        file: EncryptedFile,
+        info: Option<ImageInfo>,
+    ) -> Self {
+        Self {
+            body: body.into(),
+            #[cfg(feature = "unstable-pre-spec")]
+            filename: None,
+            #[cfg(feature = "unstable-pre-spec")]
+            sticker_like: false,
+            info: info.map(Box::new),
+            url: None,
+            file: Some(Box::new(file)),
+        }
+    }
+
+    /// Sets the metadata about the image, replacing any existing metadata.
+    pub fn with_info(mut self, info: ImageInfo) -> Self {
+        self.info = Some(Box::new(info));
+        self
+    }
+
+    /// The [BlurHash](https://blurha.sh) of the image, if any.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn blurhash(&self) -> Option<&str> {
+        self.info.as_ref().and_then(|info| info.blurhash.as_deref())
+    }
+
+    /// Creates a new message for the image at the given URL, with a default body.
+    ///
+    /// The body is derived from the mimetype in `info`, e.g. `Image.png` for `image/png`, or
+    /// is just `Image` if the mimetype is unknown.
+    pub fn with_default_body(url: impl Into<String>, info: Option<ImageInfo>) -> Self {
+        let body = default_media_body("Image", info.as_ref().and_then(|i| i.mimetype.as_deref()));
+        Self {
+            body,
+            #[cfg(feature = "unstable-pre-spec")]
+            filename: None,
+            #[cfg(feature = "unstable-pre-spec")]
+            sticker_like: false,
+            info: info.map(Box::new),
+            url: Some(url.into()),
+            file: None,
+        }
+    }
+
+    /// The filename to display for this image.
+    ///
+    /// This is `filename` if it is set, `body` otherwise.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn display_filename(&self) -> &str {
+        self.filename.as_deref().unwrap_or(&self.body)
+    }
+
+    /// Creates a new message for the image at the given URL with a caption.
+    ///
+    /// The caption is used as the `body` and the name of the image is set as the `filename`.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn with_caption_and_filename(
+        url: impl Into<String>,
+        info: Option<ImageInfo>,
+        filename: impl Into<String>,
+        caption: impl Into<String>,
+    ) -> Self {
+        Self {
+            body: caption.into(),
+            filename: Some(filename.into()),
+            ..Self::with_default_body(url, info)
+        }
+    }
+
+    /// Creates a new message for the image at the given URL that should be rendered like a
+    /// sticker, i.e. without the usual image frame.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn sticker_like(
+        body: impl Into<String>,
+        url: impl Into<String>,
+        info: Option<ImageInfo>,
+    ) -> Self {
+        Self { body: body.into(), sticker_like: true, ..Self::with_default_body(url, info) }
+    }
+}
+
+/// The payload for a location message.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LocationMessageEventContent {
+    /// A description of the location e.g. "Big Ben, London, UK,"or some kind of content description
+    /// for accessibility, e.g. "location attachment."
+    pub body: String,
+
+    /// A geo URI representing the location.
+    pub geo_uri: String,
+
+    /// Info about the location being represented.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub info: Option<Box<LocationInfo>>,
+
+    /// The type of asset being located, as described in
+    /// [MSC3488](https://github.com/matrix-org/matrix-doc/pull/3488).
+    ///
+    /// If this is `None`, the location is the one of the sender, see
+    /// [`asset_type`](#method.asset_type).
+    #[cfg(feature = "unstable-pre-spec")]
+    #[serde(
+        rename = "m.asset",
+        default,
+        with = "location_asset_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub asset: Option<LocationAssetType>,
+
+    /// The time at which the location was valid, in milliseconds since the unix epoch, as
+    /// described in [MSC3488](https://github.com/matrix-org/matrix-doc/pull/3488).
+    #[cfg(feature = "unstable-pre-spec")]
+    #[serde(rename = "m.ts", skip_serializing_if = "Option::is_none")]
+    pub ts: Option<UInt>,
+}
+
+/// Thumbnail info associated with a location.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LocationInfo {
+    /// Metadata about the image referred to in `thumbnail_url` or `thumbnail_file`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_info: Option<Box<ThumbnailInfo>>,
+
+    /// The URL to a thumbnail of the location being represented. Only present if the thumbnail is
+    /// unencrypted.
+    #[cfg_attr(feature = "compat", serde(alias = "thumb_url", alias = "thumbnailUrl"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_url: Option<String>,
+
+    /// Information on an encrypted thumbnail of the location being represented. Only present if the
+    /// thumbnail is encrypted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_file: Option<Box<EncryptedFile>>,
+}
+
+impl LocationInfo {
+    /// Whether none of the fields of this location metadata are set.
+    pub fn is_empty(&self) -> bool {
+        self.thumbnail_info.is_none()
+            && self.thumbnail_url.is_none()
+            && self.thumbnail_file.is_none()
+    }
+}
+
+impl LocationMessageEventContent {
+    /// Creates a new `LocationMessageEventContent` with the given body and geo URI.
+    pub fn new(body: impl Into<String>, geo_uri: impl Into<String>) -> Self {
+        Self {
+            body: body.into(),
+            geo_uri: geo_uri.into(),
+            info: None,
+            #[cfg(feature = "unstable-pre-spec")]
+            asset: None,
+            #[cfg(feature = "unstable-pre-spec")]
+            ts: None,
+        }
+    }
+
+    /// Parses the coordinates from `geo_uri`.
+    pub fn coordinates(&self) -> Result<GeoCoordinates, InvalidInput> {
+        self.geo_uri.parse()
+    }
+
+    /// Checks that `geo_uri` is a valid `geo` URI whose coordinates are within the valid ranges
+    /// of their coordinate reference system.
+    pub fn validate_coordinates(&self) -> Result<(), InvalidInput> {
+        self.coordinates().map(|_| ())
+    }
+
+    /// The type of asset being located, defaulting to `LocationAssetType::Self_` if `asset` is not
+    /// set.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn asset_type(&self) -> LocationAssetType {
+        self.asset.clone().unwrap_or(LocationAssetType::Self_)
+    }
+
+    /// The time at which the location was valid, converted from `ts`.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn timestamp(&self) -> Option<SystemTime> {
+        self.ts.map(|ts| UNIX_EPOCH + Duration::from_millis(ts.into()))
+    }
+}
+
+/// The type of asset being located by a location message.
+#[cfg(feature = "unstable-pre-spec")]
+#[derive(Clone, Debug, PartialEq, Eq, StringEnum)]
+pub enum LocationAssetType {
+    /// The location of the sender.
+    #[ruma_enum(rename = "m.self")]
+    Self_,
+
+    /// A location chosen by the sender, e.g. a place to meet.
+    #[ruma_enum(rename = "m.pin")]
+    Pin,
+
+    #[doc(hidden)]
+    _Custom(String),
+}
+
+/// De-/serialization of `LocationMessageEventContent::asset`, which is an object with a `type`.
+#[cfg(feature = "unstable-pre-spec")]
+mod location_asset_serde {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::LocationAssetType;
+
+    #[derive(Deserialize, Serialize)]
+    struct AssetContent<T> {
+        #[serde(rename = "type")]
+        asset_type: T,
+    }
+
+    pub fn serialize<S>(asset: &Option<LocationAssetType>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        asset.as_ref().map(|asset_type| AssetContent { asset_type }).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<LocationAssetType>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Option::<AssetContent<LocationAssetType>>::deserialize(deserializer)?
+            .map(|asset| asset.asset_type))
+    }
+}
+
+/// Coordinates parsed from a [`geo` URI](https://tools.ietf.org/html/rfc5870).
+///
+/// When parsing coordinates in the WGS-84 reference system, the latitude must be within
+/// `[-90, 90]` and the longitude within `[-180, 180]`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeoCoordinates {
+    /// The latitude in decimal degrees.
+    pub latitude: f64,
+
+    /// The longitude in decimal degrees.
+    pub longitude: f64,
+
+    /// The altitude in meters, if any.
+    pub altitude: Option<f64>,
+
+    /// The coordinate reference system of the coordinates.
+    pub crs: GeoCrs,
+
+    /// The uncertainty of the location in meters, if any.
+    pub uncertainty: Option<f64>,
+}
+
+impl FromStr for GeoCoordinates {
+    type Err = InvalidInput;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidInput(format!("invalid geo URI: {}", s));
+
+        if !s.get(..4).map_or(false, |scheme| scheme.eq_ignore_ascii_case("geo:")) {
+            return Err(invalid());
+        }
+
+        let mut parts = s[4..].split(';');
+        let coordinates = parts
+            .next()
+            .unwrap_or_default()
+            .split(',')
+            .map(|c| parse_geo_number(c).ok_or_else(invalid))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (latitude, longitude, altitude) = match coordinates.as_slice() {
+            [latitude, longitude] => (*latitude, *longitude, None),
+            [latitude, longitude, altitude] => (*latitude, *longitude, Some(*altitude)),
+            _ => return Err(invalid()),
+        };
+
+        let mut crs = GeoCrs::Wgs84;
+        let mut uncertainty = None;
+
+        for param in parts {
+            // Other parameters than `crs` and `u` may have no value.
+            let (key, value) = match param.find('=') {
+                Some(idx) => (&param[..idx], Some(&param[idx + 1..])),
+                None => (param, None),
+            };
+
+            if key.is_empty() {
+                return Err(invalid());
+            } else if key.eq_ignore_ascii_case("crs") {
+                crs = value.ok_or_else(invalid)?.to_ascii_lowercase().into();
+            } else if key.eq_ignore_ascii_case("u") {
+                uncertainty = Some(
+                    value
+                        .filter(|value| !value.starts_with('-'))
+                        .and_then(parse_geo_number)
+                        .ok_or_else(invalid)?,
+                );
+            }
+        }
+
+        if crs == GeoCrs::Wgs84
+            && !((-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude))
+        {
+            return Err(InvalidInput(format!("geo URI coordinates out of range: {}", s)));
+        }
+
+        Ok(Self { latitude, longitude, altitude, crs, uncertainty })
+    }
+}
+
+/// Parses a number in a `geo` URI, which is an optional minus sign followed by digits and an
+/// optional fraction.
+///
+/// Unlike `f64::from_str`, this rejects `NaN`, infinities and exponents.
+fn parse_geo_number(s: &str) -> Option<f64> {
+    let unsigned = if s.starts_with('-') { &s[1..] } else { s };
+    let (integer, fraction) = match unsigned.find('.') {
+        Some(idx) => (&unsigned[..idx], Some(&unsigned[idx + 1..])),
+        None => (unsigned, None),
+    };
+    let is_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+
+    if !is_digits(integer) || !fraction.map_or(true, is_digits) {
+        return None;
+    }
+
+    s.parse().ok()
+}
+
+/// The coordinate reference system of a `geo` URI.
+#[derive(Clone, Debug, PartialEq, Eq, StringEnum)]
+pub enum GeoCrs {
+    /// The World Geodetic System 1984, the default.
+    #[ruma_enum(rename = "wgs84")]
+    Wgs84,
+
+    #[doc(hidden)]
+    _Custom(String),
+}
+
+/// The payload for a notice message.
+#[derive(Clone, Debug, Deserialize)]
+pub struct NoticeMessageEventContent {
+    /// The notice text to send.
+    pub body: String,
+
+    /// Formatted form of the message `body`.
+    #[serde(flatten)]
+    pub formatted: Option<FormattedBody>,
+
+    /// Information about related messages for
+    /// [rich replies](https://matrix.org/docs/spec/client_server/r0.6.1#rich-replies).
+    #[serde(rename = "m.relates_to")]
+    pub relates_to: Option<Relation>,
+
+    /// The new content of an edited notice.
+    ///
+    /// This should only be set if `relates_to` is a `Relation::Replacement`.
+    #[cfg(feature = "unstable-pre-spec")]
+    #[serde(rename = "m.new_content")]
+    pub new_content: Option<Box<MessageEventContent>>,
+
+    /// The users and rooms this message intentionally mentions.
+    #[cfg(feature = "unstable-pre-spec")]
+    #[serde(rename = "m.mentions")]
+    pub mentions: Option<Mentions>,
+
+    /// The language of the message, as a [BCP 47](https://tools.ietf.org/html/bcp47) language
+    /// tag like `en-US`.
+    ///
+    /// Use `set_lang` to make sure the tag is valid.
+    #[cfg(feature = "unstable-pre-spec")]
+    #[serde(rename = "io.ruma.lang")]
+    pub lang: Option<String>,
+}
+
+impl NoticeMessageEventContent {
+    /// A convenience constructor to create a plain text notice.
+    pub fn plain(body: impl Into<String>) -> Self {
+        Self {
+            body: body.into(),
+            formatted: None,
+            relates_to: None,
+            #[cfg(feature = "unstable-pre-spec")]
+            new_content: None,
+            #[cfg(feature = "unstable-pre-spec")]
+            mentions: None,
+            #[cfg(feature = "unstable-pre-spec")]
+            lang: None,
+        }
+    }
+
+    /// A convenience constructor to create an html notice.
+    pub fn html(body: impl Into<String>, html_body: impl Into<String>) -> Self {
+        Self { formatted: Some(FormattedBody::html(html_body)), ..Self::plain(body) }
+    }
+
+    /// Sets or clears the language of this notice.
+    ///
+    /// Returns an error if `lang` is not a syntactically valid BCP 47 language tag.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn set_lang(&mut self, lang: Option<String>) -> Result<(), InvalidInput> {
+        if let Some(lang) = &lang {
+            if !is_language_tag(lang) {
+                return Err(InvalidInput(format!("invalid language tag `{}`", lang)));
+            }
+        }
+
+        self.lang = lang;
+        Ok(())
+    }
+
+    /// Turns this notice into an edit of the notice with the given event ID.
+    ///
+    /// This works like [`TextMessageEventContent::make_replacement`], with this notice as the
+    /// `m.new_content` of the edit.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn make_replacement(self, original_event_id: EventId) -> Self {
+        let mut replacement = Self::plain(format!("* {}", self.body));
+        replacement.formatted = self.formatted.as_ref().map(|formatted| FormattedBody {
+            format: formatted.format.clone(),
+            body: format!("* {}", formatted.body),
+        });
+        replacement.relates_to =
+            Some(Relation::Replacement(Replacement { event_id: original_event_id }));
+        replacement.new_content =
+            Some(Box::new(MessageEventContent::Notice(Self { relates_to: None, ..self })));
+
+        replacement
+    }
+}
+
+/// The intentional mentions of a message, as proposed in
+/// [MSC3952](https://github.com/matrix-org/matrix-spec-proposals/pull/3952).
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[cfg(feature = "unstable-pre-spec")]
+pub struct Mentions {
+    /// The IDs of the users that are mentioned.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub user_ids: Vec<UserId>,
+
+    /// Whether the whole room is mentioned, e.g. with `@room`.
+    #[serde(default, skip_serializing_if = "ruma_serde::is_default")]
+    pub room: bool,
+}
+
+#[cfg(feature = "unstable-pre-spec")]
+impl Mentions {
+    /// Creates a new `Mentions` that doesn't mention anyone.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the whole room is mentioned.
+    pub fn mentions_room(&self) -> bool {
+        self.room
+    }
+
+    /// Whether the user with the given ID is mentioned explicitly.
+    ///
+    /// This doesn't take room mentions into account, use [`Mentions::mentions_room`] for those.
+    pub fn mentions_user(&self, user_id: &UserId) -> bool {
+        self.user_ids.contains(user_id)
+    }
+}
+
+/// A preview of a URL in a message, bundled by the sender as proposed in
+/// [MSC4095](https://github.com/matrix-org/matrix-spec-proposals/pull/4095).
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[cfg(feature = "unstable-pre-spec")]
+pub struct UrlPreview {
+    /// The URL in the message body that this preview is for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matched_url: Option<String>,
+
+    /// The title of the linked page.
+    #[serde(rename = "og:title", skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    /// The description of the linked page.
+    #[serde(rename = "og:description", skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// The MXC URI of the preview image, uploaded by the sender.
+    #[serde(rename = "og:image", skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+#[cfg(feature = "unstable-pre-spec")]
+impl UrlPreview {
+    /// Creates a new `UrlPreview` for the given URL, without any preview data.
+    pub fn new(matched_url: impl Into<String>) -> Self {
+        Self { matched_url: Some(matched_url.into()), ..Self::default() }
+    }
+}
+
+/// The payload for a server notice message.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ServerNoticeMessageEventContent {
+    /// A human-readable description of the notice.
+    pub body: String,
+
+    /// The type of notice being represented.
+    pub server_notice_type: ServerNoticeType,
+
+    /// A URI giving a contact method for the server administrator.
+    ///
+    /// Required if the notice type is `m.server_notice.usage_limit_reached`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_contact: Option<String>,
+
+    /// The kind of usage limit the server has exceeded.
+    ///
+    /// Required if the notice type is `m.server_notice.usage_limit_reached`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit_type: Option<LimitType>,
+
+    /// Information about related messages, e.g. an earlier notice that this one updates.
+    #[serde(rename = "m.relates_to", skip_serializing_if = "Option::is_none")]
+    pub relates_to: Option<Relation>,
+}
+
+impl ServerNoticeMessageEventContent {
+    /// Creates a new server notice with the given body and notice type, without the fields that
+    /// are specific to usage limit notices.
+    ///
+    /// This is meant for notice types other than `m.server_notice.usage_limit_reached`. If that
+    /// type is given anyway, it is recognized as `ServerNoticeType::UsageLimitReached`.
+    pub fn custom(body: impl Into<String>, server_notice_type: impl Into<String>) -> Self {
+        Self {
+            body: body.into(),
+            server_notice_type: ServerNoticeType::from(server_notice_type.into()),
+            admin_contact: None,
+            limit_type: None,
+            relates_to: None,
+        }
+    }
+}
+
+/// Types of server notices.
+#[derive(Clone, Debug, PartialEq, Eq, StringEnum)]
+pub enum ServerNoticeType {
+    /// The server has exceeded some limit which requires the server administrator to intervene.
+    #[ruma_enum(rename = "m.server_notice.usage_limit_reached")]
+    UsageLimitReached,
+
+    #[doc(hidden)]
+    _Custom(String),
+}
+
+/// Types of usage limits.
+#[derive(Clone, Debug, PartialEq, Eq, StringEnum)]
+#[ruma_enum(rename_all = "snake_case")]
+pub enum LimitType {
+    /// The server's number of active users in the last 30 days has exceeded the maximum.
+    ///
+    /// New connections are being refused by the server. What defines "active" is left as an
+    /// implementation detail, however servers are encouraged to treat syncing users as "active".
+    MonthlyActiveUser,
+
+    #[doc(hidden)]
+    _Custom(String),
+}
+
+impl FromStr for ServerNoticeType {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.into())
+    }
+}
+
+impl FromStr for LimitType {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.into())
+    }
+}
+
+/// The format for the formatted representation of a message body.
+///
+/// This type can hold an arbitrary string. To check for events that are not
+/// available as a documented variant here, use its string representation,
+/// obtained through `.as_str()`.
+#[derive(Clone, Debug, PartialEq, Eq, StringEnum)]
+pub enum MessageFormat {
+    /// HTML.
+    #[ruma_enum(rename = "org.matrix.custom.html")]
+    Html,
+
+    #[doc(hidden)]
+    _Custom(String),
+}
+
+impl MessageFormat {
+    /// Creates a string slice from this `MessageFormat`.
+    pub fn as_str(&self) -> &str {
+        self.as_ref()
+    }
+}
+
+impl FromStr for MessageFormat {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.into())
+    }
+}
+
+/// Common message event content fields for message types that have separate plain-text and
+/// formatted representations.
+///
+/// When deserializing message content that is flattened into, `format` without `formatted_body`
+/// (or `formatted_body` without `format`) is treated as if neither was present, so the message
+/// content is deserialized with `formatted: None` instead of failing.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FormattedBody {
+    /// The format used in the `formatted_body`.
+    pub format: MessageFormat,
+
+    /// The formatted version of the `body`.
+    #[serde(rename = "formatted_body")]
+    pub body: String,
+}
+
+impl FormattedBody {
+    /// Creates a new HTML-formatted message body.
+    pub fn html(body: impl Into<String>) -> Self {
+        Self { format: MessageFormat::Html, body: body.into() }
+    }
+
+    /// Quotes the given text.
+    ///
+    /// Returns the plain-text quote, with every line prefixed by `> `, and the HTML quote, a
+    /// `<blockquote>` with the escaped text.
+    pub fn quote(text: &str) -> (String, FormattedBody) {
+        let plain = text.lines().map(|line| format!("> {}", line)).collect::<Vec<_>>().join("\n");
+        let html = format!("<blockquote>{}</blockquote>", escape_plain_body(text));
+
+        (plain, Self::html(html))
+    }
+
+    /// Creates an HTML body with the given text in the given foreground and background colors.
+    ///
+    /// Colors must be of the form `#rrggbb`, otherwise an error is returned.
+    pub fn colored(text: &str, fg: Option<&str>, bg: Option<&str>) -> Result<Self, InvalidInput> {
+        let mut html = "<font".to_owned();
+        for (attr, color) in &[("data-mx-color", fg), ("data-mx-bg-color", bg)] {
+            if let Some(color) = color {
+                if !is_hex_color(color) {
+                    return Err(InvalidInput(format!(
+                        "invalid color `{}`, expected `#rrggbb`",
+                        color
+                    )));
+                }
+                html.push_str(&format!(" {}=\"{}\"", attr, color));
+            }
+        }
+        html.push('>');
+        html.push_str(&escape_plain_body(text));
+        html.push_str("</font>");
+
+        Ok(Self::html(html))
+    }
+
+    /// Computes the deepest nesting of HTML elements in this formatted body.
+    ///
+    /// Void elements like `<br>` don't add to the depth. End tags without a matching start tag
+    /// are ignored and elements that are never closed count as open until the end of the body.
+    #[cfg(feature = "html")]
+    pub fn max_nesting_depth(&self) -> usize {
+        let mut depth = 0_usize;
+        let mut max_depth = 0_usize;
+
+        for token in html::Tokenizer::new(&self.body) {
+            if token.opens_element() {
+                depth += 1;
+                max_depth = max_depth.max(depth);
+            } else if let html::Token::EndTag { .. } = token {
+                depth = depth.saturating_sub(1);
+            }
+        }
+
+        max_depth
+    }
+
+    /// Sanitizes the HTML of this formatted body according to the allow-list of the
+    /// [specification](https://matrix.org/docs/spec/client_server/r0.6.1#m-room-message-msgtypes).
+    ///
+    /// Disallowed elements, attributes and comments are removed, as are elements nested deeper
+    /// than 100 levels. Elements that are not closed are closed at the end of the body. Does
+    /// nothing if the format of this body is not HTML.
+    #[cfg(feature = "html")]
+    pub fn sanitize(&mut self) {
+        self.sanitize_with(&SanitizerConfig::default())
+    }
+
+    /// Sanitizes the HTML of this formatted body according to the given configuration.
+    ///
+    /// See [`sanitize`](#method.sanitize) for details.
+    #[cfg(feature = "html")]
+    pub fn sanitize_with(&mut self, config: &SanitizerConfig) {
+        if self.format == MessageFormat::Html {
+            let mut sanitized = String::with_capacity(self.body.len());
+            self.write_sanitized_with(config, &mut sanitized)
+                .expect("writing to a String can't fail");
+            self.body = sanitized;
+        }
+    }
+
+    /// Writes the sanitized HTML of this formatted body to `out`.
+    ///
+    /// The body is processed as a stream of tokens without building a document tree, so this is
+    /// suitable for very large bodies. See [`sanitize`](#method.sanitize) for details.
+    #[cfg(feature = "html")]
+    pub fn write_sanitized(&self, out: &mut impl fmt::Write) -> fmt::Result {
+        self.write_sanitized_with(&SanitizerConfig::default(), out)
+    }
+
+    /// Writes the HTML of this formatted body, sanitized according to the given configuration, to
+    /// `out`.
+    #[cfg(feature = "html")]
+    pub fn write_sanitized_with(
+        &self,
+        config: &SanitizerConfig,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result {
+        html::sanitize(&self.body, config, out)
+    }
+
+    /// Converts this formatted body to plain text.
+    ///
+    /// For HTML, markup is removed, character references are decoded and line breaks and block
+    /// elements start a new line. The rich reply fallback is removed as well. Bodies in other
+    /// formats are returned unchanged.
+    #[cfg(feature = "html")]
+    pub fn plain_text(&self) -> String {
+        if self.format == MessageFormat::Html {
+            html::plain_text(&self.body)
+        } else {
+            self.body.clone()
+        }
+    }
+
+    /// Extracts the code blocks, i.e. `<code>` elements inside `<pre>` elements, of this formatted
+    /// body.
+    ///
+    /// Returns the language of each block, taken from a `language-` class of the `<code>` element,
+    /// and its text content. Inline `<code>` elements are not included.
+    #[cfg(feature = "html")]
+    pub fn code_blocks(&self) -> Vec<(Option<String>, String)> {
+        let mut blocks = Vec::new();
+        let mut pre_depth = 0_usize;
+        let mut current_block: Option<(Option<String>, String)> = None;
+
+        for token in html::Tokenizer::new(&self.body) {
+            match &token {
+                html::Token::StartTag { name, .. } if name == "pre" => pre_depth += 1,
+                html::Token::StartTag { name, .. }
+                    if name == "code" && pre_depth > 0 && current_block.is_none() =>
+                {
+                    let language = token.attr("class").and_then(|class| {
+                        class
+                            .split_whitespace()
+                            .find(|class| class.starts_with("language-"))
+                            .map(|class| class["language-".len()..].to_owned())
+                    });
+                    current_block = Some((language, String::new()));
+                }
+                html::Token::EndTag { name } if name == "code" || name == "pre" => {
+                    if name == "pre" {
+                        pre_depth = pre_depth.saturating_sub(1);
+                    }
+                    blocks.extend(current_block.take());
+                }
+                html::Token::Text(text) => {
+                    if let Some((_, content)) = &mut current_block {
+                        content.push_str(&html::decode_entities(text));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        blocks
+    }
+
+    /// Extracts the headings, i.e. `<h1>` to `<h6>` elements, of this formatted body in document
+    /// order.
+    ///
+    /// Returns the level of each heading and its text content, e.g. `(2, "Usage")` for
+    /// `<h2>Usage</h2>`.
+    #[cfg(feature = "html")]
+    pub fn headings(&self) -> Vec<(u8, String)> {
+        fn heading_level(name: &str) -> Option<u8> {
+            match name {
+                "h1" => Some(1),
+                "h2" => Some(2),
+                "h3" => Some(3),
+                "h4" => Some(4),
+                "h5" => Some(5),
+                "h6" => Some(6),
+                _ => None,
+            }
+        }
+
+        let mut headings = Vec::new();
+        let mut current_heading: Option<(u8, String)> = None;
+
+        for token in html::Tokenizer::new(&self.body) {
+            match &token {
+                html::Token::StartTag { name, .. } if current_heading.is_none() => {
+                    if let Some(level) = heading_level(name) {
+                        current_heading = Some((level, String::new()));
+                    }
+                }
+                html::Token::EndTag { name } if heading_level(name).is_some() => {
+                    headings.extend(current_heading.take());
+                }
+                html::Token::Text(text) => {
+                    if let Some((_, content)) = &mut current_heading {
+                        content.push_str(&html::decode_entities(text));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        headings
+    }
+
+    /// Extracts the collapsible sections, i.e. `<details>` elements, of this formatted body.
+    ///
+    /// Returns the text of the `<summary>` of each section and the HTML of the rest of its
+    /// content. To keep these sections when sanitizing, use
+    /// [`SanitizerConfig::allow_collapsible_sections`].
+    #[cfg(feature = "html")]
+    pub fn collapsible_sections(&self) -> Vec<(String, String)> {
+        if self.format == MessageFormat::Html {
+            html::collapsible_sections(&self.body)
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Rewrites shorthand `matrix.to` links in this formatted body into absolute links starting
+    /// with `base`, usually `https://matrix.to`.
+    ///
+    /// This rewrites the `href` of links that are protocol-relative, like
+    /// `//matrix.to/#/@user:example.org`, that have no scheme, like
+    /// `matrix.to/#/@user:example.org`, or that are bare identifiers, like `#room:example.org`.
+    #[cfg(feature = "html")]
+    pub fn absolutize_matrix_links(&mut self, base: &str) {
+        if self.format == MessageFormat::Html {
+            self.body = html::absolutize_matrix_links(&self.body, base);
+        }
+    }
+
+    /// Checks that HTML elements in this formatted body are not nested deeper than `limit`.
+    ///
+    /// The specification recommends a limit of 100 levels.
+    #[cfg(feature = "html")]
+    pub fn validate_depth(&self, limit: usize) -> Result<(), InvalidInput> {
+        let depth = self.max_nesting_depth();
+        if depth > limit {
+            Err(InvalidInput(format!(
+                "formatted body is nested {} levels deep, but the limit is {}",
+                depth, limit
+            )))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The payload for a text message.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TextMessageEventContent {
+    /// The body of the message.
+    pub body: String,
+
+    /// Formatted form of the message `body`.
+    #[serde(flatten)]
+    pub formatted: Option<FormattedBody>,
+
+    /// Information about related messages for
+    /// [rich replies](https://matrix.org/docs/spec/client_server/r0.6.1#rich-replies).
+    #[serde(rename = "m.relates_to")]
+    pub relates_to: Option<Relation>,
+
+    /// The new content of an edited message.
+    ///
+    /// This should only be set if `relates_to` is a `Relation::Replacement`.
+    #[cfg(feature = "unstable-pre-spec")]
+    #[serde(rename = "m.new_content")]
+    pub new_content: Option<Box<MessageEventContent>>,
+
+    /// The users and rooms this message intentionally mentions.
+    #[cfg(feature = "unstable-pre-spec")]
+    #[serde(rename = "m.mentions")]
+    pub mentions: Option<Mentions>,
+
+    /// The language of the message, as a [BCP 47](https://tools.ietf.org/html/bcp47) language
+    /// tag like `en-US`.
+    ///
+    /// Use `set_lang` to make sure the tag is valid.
+    #[cfg(feature = "unstable-pre-spec")]
+    #[serde(rename = "io.ruma.lang")]
+    pub lang: Option<String>,
+
+    /// Previews of the URLs in the message, bundled by the sender so receiving clients don't
+    /// have to request them.
+    ///
+    /// An empty list means that the sender explicitly didn't want any previews to be shown.
+    #[cfg(feature = "unstable-pre-spec")]
+    #[serde(rename = "m.url_previews")]
+    pub url_previews: Option<Vec<UrlPreview>>,
+}
+
+impl TextMessageEventContent {
+    /// A convenience constructor to create a plain text message.
+    pub fn plain(body: impl Into<String>) -> Self {
+        Self {
+            body: body.into(),
+            formatted: None,
+            relates_to: None,
+            #[cfg(feature = "unstable-pre-spec")]
+            new_content: None,
+            #[cfg(feature = "unstable-pre-spec")]
+            mentions: None,
+            #[cfg(feature = "unstable-pre-spec")]
+            lang: None,
+            #[cfg(feature = "unstable-pre-spec")]
+            url_previews: None,
+        }
+    }
+
+    /// A convenience constructor to create an html message.
+    pub fn html(body: impl Into<String>, html_body: impl Into<String>) -> Self {
+        Self { formatted: Some(FormattedBody::html(html_body)), ..Self::plain(body) }
+    }
+
+    /// Sets the bundled previews of the URLs in this message, replacing any existing ones.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn with_url_previews(mut self, url_previews: Vec<UrlPreview>) -> Self {
+        self.url_previews = Some(url_previews);
+        self
+    }
+
+    /// Sets or clears the language of this message.
+    ///
+    /// Returns an error if `lang` is not a syntactically valid BCP 47 language tag.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn set_lang(&mut self, lang: Option<String>) -> Result<(), InvalidInput> {
+        if let Some(lang) = &lang {
+            if !is_language_tag(lang) {
+                return Err(InvalidInput(format!("invalid language tag `{}`", lang)));
+            }
+        }
+
+        self.lang = lang;
+        Ok(())
+    }
+
+    /// Turns this message into an edit of the message with the given event ID.
+    ///
+    /// This message becomes the `m.new_content` of the edit unchanged. The fallback for clients
+    /// that don't support edits is the body and formatted body prefixed with `* `, so an edit that
+    /// only changes the formatting keeps the same plain-text body.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn make_replacement(self, original_event_id: EventId) -> Self {
+        let mut replacement = Self::plain(format!("* {}", self.body));
+        replacement.formatted = self.formatted.as_ref().map(|formatted| FormattedBody {
+            format: formatted.format.clone(),
+            body: format!("* {}", formatted.body),
+        });
+        replacement.relates_to =
+            Some(Relation::Replacement(Replacement { event_id: original_event_id }));
+        replacement.new_content =
+            Some(Box::new(MessageEventContent::Text(Self { relates_to: None, ..self })));
+
+        replacement
+    }
+
+    /// Returns the text representations of this message as `(mimetype, content)` pairs.
+    ///
+    /// The plain-text body comes first as `text/plain`, followed by the formatted body as
+    /// `text/html` if there is one in the HTML format. Formatted bodies in other formats are not
+    /// included since they have no known mimetype.
+    pub fn representations(&self) -> Vec<(&str, &str)> {
+        let mut representations = vec![("text/plain", self.body.as_str())];
+        if let Some(formatted) = &self.formatted {
+            if formatted.format == MessageFormat::Html {
+                representations.push(("text/html", formatted.body.as_str()));
+            }
+        }
+
+        representations
+    }
+
+    /// Whether the body or formatted body of this message contains invisible characters that are
+    /// commonly used to evade spam filters.
+    ///
+    /// These are zero-width spaces, zero-width joiners outside of emoji sequences, the byte order
+    /// mark and directional formatting characters like the right-to-left override.
+    pub fn contains_invisible_chars(&self) -> bool {
+        std::iter::once(&self.body)
+            .chain(self.formatted.as_ref().map(|formatted| &formatted.body))
+            .any(|text| strip_invisible_chars(text).len() != text.len())
+    }
+
+    /// Removes the characters detected by
+    /// [`contains_invisible_chars`](#method.contains_invisible_chars) from the body and formatted
+    /// body of this message.
+    pub fn strip_invisible_chars(&mut self) {
+        self.body = strip_invisible_chars(&self.body);
+        if let Some(formatted) = &mut self.formatted {
+            formatted.body = strip_invisible_chars(&formatted.body);
+        }
+    }
+
+    /// Fills the body of this message from its formatted body if the body is empty.
+    ///
+    /// This regenerates a plain-text body for messages that only have a meaningful formatted body.
+    /// Does nothing if the body is not empty or whitespace, or if there is no formatted body.
+    #[cfg(feature = "html")]
+    pub fn ensure_plain_body(&mut self) {
+        if self.body.trim().is_empty() {
+            if let Some(formatted) = &self.formatted {
+                self.body = formatted.plain_text();
+            }
+        }
+    }
+
+    /// Returns the number of grapheme clusters in the body of this message, without the reply
+    /// fallback.
+    ///
+    /// This is closer to the displayed length of the message than its length in bytes or chars,
+    /// e.g. an emoji ZWJ sequence counts as a single grapheme.
+    #[cfg(feature = "unicode")]
+    pub fn grapheme_len(&self) -> usize {
+        body_without_reply_fallback(&self.body, self.relates_to.as_ref()).graphemes(true).count()
+    }
+
+    /// Returns the emoji in the body of this message, without the reply fallback.
+    ///
+    /// The emoji are returned in order, including duplicates. Sequences like flags or families
+    /// joined by ZWJs are returned as a single emoji.
+    #[cfg(feature = "unicode")]
+    pub fn emojis(&self) -> Vec<String> {
+        body_without_reply_fallback(&self.body, self.relates_to.as_ref())
+            .graphemes(true)
+            .filter(|grapheme| is_emoji_grapheme(grapheme))
+            .map(ToOwned::to_owned)
+            .collect()
+    }
+
+    /// How many levels of reply fallbacks are nested in this message.
+    ///
+    /// This is `0` for a message that is not a reply, `1` for a reply to a message that is not a
+    /// reply itself, and so on. The nesting of `<mx-reply>` elements is used if the message has a
+    /// formatted body, otherwise the nesting of `> ` quotes in the plain body. Clients can use
+    /// this to collapse deep reply chains.
+    pub fn reply_fallback_depth(&self) -> usize {
+        match &self.formatted {
+            Some(formatted) if formatted.body.starts_with("<mx-reply>") => {
+                html_reply_fallback_depth(&formatted.body)
+            }
+            Some(_) => 0,
+            None => plain_reply_fallback_depth(&self.body),
+        }
+    }
+
+    /// The ID of the room of the replied-to event, taken from the `matrix.to` link in the
+    /// `<mx-reply>` rich reply fallback of the formatted body.
+    ///
+    /// Clients can compare this with the room the message was sent in to detect inconsistent
+    /// replies. Returns `None` if there is no formatted reply fallback or it doesn't link to a
+    /// valid room ID.
+    pub fn reply_fallback_room(&self) -> Option<RoomId> {
+        let html = &self.formatted.as_ref()?.body;
+        let fallback_len = html.len() - strip_html_reply_fallback(html).len();
+        matrix_to_room_id(&html[..fallback_len])
+    }
+
+    /// Replaces the user-authored part of the body, keeping the rich reply fallback if there is
+    /// one.
+    ///
+    /// If this message has a formatted body, its `<mx-reply>` fallback is kept as well and the
+    /// rest of it is replaced by the escaped `new_visible_body`.
+    pub fn set_body_preserving_fallback(&mut self, new_visible_body: &str) {
+        let fallback_len = self.body.len()
+            - body_without_reply_fallback(&self.body, self.relates_to.as_ref()).len();
+        self.body.truncate(fallback_len);
+        self.body.push_str(new_visible_body);
+
+        if let Some(formatted) = &mut self.formatted {
+            const REPLY_END: &str = "</mx-reply>";
+            let fallback_len = if formatted.body.starts_with("<mx-reply>") {
+                formatted.body.find(REPLY_END).map_or(0, |i| i + REPLY_END.len())
+            } else {
+                0
+            };
+
+            formatted.body.truncate(fallback_len);
+            formatted.body.push_str(&escape_plain_body(new_visible_body));
+        }
+    }
+
+    /// Appends the content of `other` to this message.
+    ///
+    /// The bodies are joined with a newline and the formatted bodies with `<br>`. If only one of
+    /// the messages has a formatted body, the plain body of the other one is escaped and used in
+    /// its place. Any relation of this message is dropped, since it doesn't apply to the combined
+    /// content.
+    pub fn append(&mut self, other: &TextMessageEventContent) {
+        self.formatted = match (self.formatted.take(), &other.formatted) {
+            (None, None) => None,
+            (formatted, other_formatted) => {
+                let format = formatted
+                    .as_ref()
+                    .or(other_formatted.as_ref())
+                    .map(|f| f.format.clone())
+                    .unwrap_or(MessageFormat::Html);
+                let first = formatted.map_or_else(|| escape_plain_body(&self.body), |f| f.body);
+                let second = other_formatted
+                    .as_ref()
+                    .map_or_else(|| escape_plain_body(&other.body), |f| f.body.clone());
+
+                Some(FormattedBody { format, body: format!("{}<br>{}", first, second) })
+            }
+        };
+
+        self.body = format!("{}\n{}", self.body, other.body);
+        self.relates_to = None;
+
+        #[cfg(feature = "unstable-pre-spec")]
+        {
+            self.new_content = None;
+        }
+    }
+
+    /// Creates a plain text reply to the given message, with a rich reply fallback.
+    pub fn reply_to(original: &MessageEvent, body: impl Into<String>) -> Self {
+        Self::reply_to_with_fallback(original, body, true)
+    }
+
+    /// Creates a plain text reply to the given message.
+    ///
+    /// If `include_fallback` is `false`, the body is just the given text, without the quote of
+    /// the original message. This is meant for clients that know that the recipients support rich
+    /// replies.
+    pub fn reply_to_with_fallback(
+        original: &MessageEvent,
+        body: impl Into<String>,
+        include_fallback: bool,
+    ) -> Self {
+        let body = body.into();
+        let body = if include_fallback {
+            let original_body =
+                body_without_reply_fallback(original.content.body(), original.content.relates_to());
+            plain_reply_fallback(&original.sender, original_body) + &body
+        } else {
+            body
+        };
+
+        Self {
+            relates_to: Some(Relation::Reply {
+                in_reply_to: InReplyTo::new(original.event_id.clone()),
+                custom: BTreeMap::new(),
+            }),
+            ..Self::plain(body)
+        }
+    }
+
+    /// A convenience constructor to create a plain text message.
+    #[deprecated = "Renamed to plain"]
+    pub fn new_plain(body: impl Into<String>) -> Self {
+        Self::plain(body)
+    }
+}
+
+/// A builder for text messages in a thread, optionally replying to another event in the thread.
+///
+/// Without [`reply_to`](#method.reply_to), the message is a plain thread message whose reply to
+/// the latest event of the thread is only a fallback for clients that don't support threads. With
+/// it, the message is a genuine reply and its body gets the plain-text rich reply fallback.
+#[cfg(feature = "unstable-pre-spec")]
+#[derive(Clone, Debug)]
+pub struct ThreadReplyBuilder {
+    thread_root: EventId,
+    latest_event_id: EventId,
+    body: String,
+    reply_to: Option<(EventId, String)>,
+}
+
+#[cfg(feature = "unstable-pre-spec")]
+impl ThreadReplyBuilder {
+    /// Creates a new `ThreadReplyBuilder` for a message with the given body in the thread with
+    /// the given root, whose latest event has the ID `latest_event_id`.
+    pub fn new(thread_root: EventId, latest_event_id: EventId, body: impl Into<String>) -> Self {
+        Self { thread_root, latest_event_id, body: body.into(), reply_to: None }
+    }
+
+    /// Makes the message a reply to the event with the given ID, sender and body.
+    pub fn reply_to(mut self, event_id: EventId, sender: &UserId, body: &str) -> Self {
+        // Only the body of the replied-to message is known, so its fallback has to be detected
+        // from the body alone.
+        self.reply_to =
+            Some((event_id, plain_reply_fallback(sender, strip_plain_reply_fallback(body))));
+        self
+    }
+
+    /// Builds the text message.
+    pub fn build(self) -> TextMessageEventContent {
+        let (body, thread) = match self.reply_to {
+            Some((event_id, fallback)) => {
+                (fallback + &self.body, Thread::reply(self.thread_root, event_id))
+            }
+            None => (self.body, Thread::plain(self.thread_root, self.latest_event_id)),
+        };
+
+        TextMessageEventContent {
+            relates_to: Some(Relation::Thread(thread)),
+            ..TextMessageEventContent::plain(body)
+        }
+    }
+}
+
+/// The payload for a video message.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct VideoMessageEventContent {
+    /// A description of the video, e.g. "Gangnam Style," or some kind of content description for
+    /// accessibility, e.g. "video attachment."
+    pub body: String,
+
+    /// The original filename of the uploaded video clip, if it differs from `body`.
+    ///
+    /// If this is set, `body` can be used as a caption.
+    #[cfg(feature = "unstable-pre-spec")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+
+    /// Metadata about the video clip referred to in `url`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub info: Option<Box<VideoInfo>>,
+
+    /// The URL to the video clip.  Required if the file is unencrypted. The URL (typically
+    /// [MXC URI](https://matrix.org/docs/spec/client_server/r0.6.1#mxc-uri)) to the video clip.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+
+    /// Required if video clip is encrypted. Information on the encrypted video clip.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<Box<EncryptedFile>>,
+}
+
+impl VideoMessageEventContent {
+    /// Creates a new message for an encrypted video clip, without a `url`.
+    pub fn encrypted(
+        body: impl Into<String>,
+        file: EncryptedFile,
+        info: Option<VideoInfo>,
+    ) -> Self {
+        Self {
+            body: body.into(),
+            #[cfg(feature = "unstable-pre-spec")]
+            filename: None,
+            info: info.map(Box::new),
+            url: None,
+            file: Some(Box::new(file)),
+        }
+    }
+
+    /// Sets the metadata about the video clip, replacing any existing metadata.
+    pub fn with_info(mut self, info: VideoInfo) -> Self {
+        self.info = Some(Box::new(info));
+        self
+    }
+
+    /// The [BlurHash](https://blurha.sh) of the video clip, if any.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn blurhash(&self) -> Option<&str> {
+        self.info.as_ref().and_then(|info| info.blurhash.as_deref())
+    }
+
+    /// Creates a new message for the video clip at the given URL, with a default body.
+    ///
+    /// The body is derived from the mimetype in `info`, e.g. `Video.mp4` for `video/mp4`, or
+    /// is just `Video` if the mimetype is unknown.
+    pub fn with_default_body(url: impl Into<String>, info: Option<VideoInfo>) -> Self {
+        let body = default_media_body("Video", info.as_ref().and_then(|i| i.mimetype.as_deref()));
+        Self {
+            body,
+            #[cfg(feature = "unstable-pre-spec")]
+            filename: None,
+            info: info.map(Box::new),
+            url: Some(url.into()),
+            file: None,
+        }
+    }
+
+    /// The filename to display for this video clip.
+    ///
+    /// This is `filename` if it is set, `body` otherwise.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn display_filename(&self) -> &str {
+        self.filename.as_deref().unwrap_or(&self.body)
+    }
+
+    /// Creates a new message for the video at the given URL with a caption.
+    ///
+    /// The caption is used as the `body` and the name of the video is set as the `filename`.
+    #[cfg(feature = "unstable-pre-spec")]
+    pub fn with_caption_and_filename(
+        url: impl Into<String>,
+        info: Option<VideoInfo>,
+        filename: impl Into<String>,
+        caption: impl Into<String>,
+    ) -> Self {
+        Self {
+            body: caption.into(),
+            filename: Some(filename.into()),
+            ..Self::with_default_body(url, info)
+        }
+    }
+}
+
+/// Metadata about a video.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct VideoInfo {
+    /// The duration of the video in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<UInt>,
+
+    /// The height of the video in pixels.
+    #[serde(rename = "h")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<UInt>,
+
+    /// The width of the video in pixels.
+    #[serde(rename = "w")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<UInt>,
+
+    /// The mimetype of the video, e.g. "video/mp4."
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mimetype: Option<String>,
+
+    /// The size of the video in bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<UInt>,
+
+    /// Metadata about an image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_info: Option<Box<ThumbnailInfo>>,
+
+    /// The URL (typically [MXC URI](https://matrix.org/docs/spec/client_server/r0.6.1#mxc-uri)) to
+    /// an image thumbnail of the video clip. Only present if the thumbnail is unencrypted.
+    #[cfg_attr(feature = "compat", serde(alias = "thumb_url", alias = "thumbnailUrl"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_url: Option<String>,
+
+    /// Information on the encrypted thumbnail file.  Only present if the thumbnail is encrypted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_file: Option<Box<EncryptedFile>>,
+
+    /// The [BlurHash](https://blurha.sh) for this video.
+    ///
+    /// This uses the unstable prefix in
+    /// [MSC2448](https://github.com/matrix-org/matrix-doc/pull/2448).
+    #[cfg(feature = "unstable-pre-spec")]
+    #[serde(rename = "xyz.amorgan.blurhash", skip_serializing_if = "Option::is_none")]
+    pub blurhash: Option<String>,
+}
+
+impl MediaInfo for VideoInfo {
+    fn mimetype(&self) -> Option<&str> {
+        self.mimetype.as_deref()
+    }
+
+    fn size(&self) -> Option<UInt> {
+        self.size
+    }
+}
+
+impl VideoInfo {
+    /// The length in bytes of the JSON serialization of this video metadata.
+    pub fn serialized_len(&self) -> usize {
+        serde_json::to_vec(self).expect("media info serialization can't fail").len()
+    }
+
+    /// Whether none of the fields of this video metadata are set.
+    pub fn is_empty(&self) -> bool {
+        self.duration.is_none()
+            && self.height.is_none()
+            && self.width.is_none()
+            && self.mimetype.is_none()
+            && self.size.is_none()
+            && self.thumbnail_info.is_none()
+            && self.thumbnail_url.is_none()
+            && self.thumbnail_file.is_none()
+            && self.blurhash_is_none()
+    }
+
+    #[cfg(feature = "unstable-pre-spec")]
+    fn blurhash_is_none(&self) -> bool {
+        self.blurhash.is_none()
+    }
+
+    #[cfg(not(feature = "unstable-pre-spec"))]
+    fn blurhash_is_none(&self) -> bool {
+        true
+    }
+
+    /// Whether the declared size or duration of the video exceeds the given limits.
+    ///
+    /// Metadata that is not present is not considered to exceed any limit.
+    pub fn exceeds(&self, max_size: UInt, max_duration_ms: UInt) -> bool {
+        self.size.map_or(false, |size| size > max_size)
+            || self.duration.map_or(false, |duration| duration > max_duration_ms)
+    }
+}
+
+/// The text content of an extensible event.
+#[cfg(feature = "unstable-pre-spec")]
+#[derive(Deserialize)]
+struct ExtensibleTextContent {
+    #[serde(rename = "m.message", default)]
+    message: Vec<ExtensibleTextRepresentation>,
+
+    #[serde(rename = "m.text")]
+    text: Option<String>,
+
+    #[serde(rename = "m.html")]
+    html: Option<String>,
+}
+
+/// A single representation of the text of an extensible event.
+#[cfg(feature = "unstable-pre-spec")]
+#[derive(Deserialize)]
+struct ExtensibleTextRepresentation {
+    #[serde(default = "default_text_mimetype")]
+    mimetype: String,
+
+    body: String,
+}
+
+#[cfg(feature = "unstable-pre-spec")]
+fn default_text_mimetype() -> String {
+    "text/plain".to_owned()
+}
+
+/// Sets `info` to `None` if it is empty according to `is_empty`.
+fn prune_info<T>(info: &mut Option<Box<T>>, is_empty: fn(&T) -> bool) {
+    if info.as_deref().map_or(false, is_empty) {
+        *info = None;
+    }
+}
+
+/// Creates a body like `Image.png` for a media message from the kind of media and its mimetype.
+fn default_media_body(kind: &str, mimetype: Option<&str>) -> String {
+    let extension = mimetype
+        .and_then(|mimetype| mimetype.split(';').next())
+        .and_then(|essence| essence.split('/').nth(1))
+        .and_then(|subtype| subtype.split('+').next())
+        .map(str::trim)
+        .filter(|extension| !extension.is_empty());
+
+    match extension {
+        Some(extension) => format!("{}.{}", kind, extension.to_ascii_lowercase()),
+        None => kind.to_owned(),
+    }
+}
+
+/// Whether the given string is a color of the form `#rrggbb`.
+fn is_hex_color(color: &str) -> bool {
+    color.len() == 7 && color.starts_with('#') && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Escapes a plain-text body so it can be embedded in an HTML formatted body.
+fn escape_plain_body(body: &str) -> String {
+    body.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('\n', "<br>")
+}
+
+/// Calls `f` with the value of `value` if there is one.
+fn visit_opt(value: &Option<String>, f: impl FnOnce(&str)) {
+    if let Some(value) = value {
+        f(value);
+    }
+}
+
+/// Visits the URL of a piece of media, whether it is encrypted or not.
+fn visit_media(
+    visitor: &mut impl MessageVisitor,
+    url: &Option<String>,
+    file: &Option<Box<EncryptedFile>>,
+) {
+    visit_opt(url, |url| visitor.on_url(url));
+    if let Some(file) = file {
+        visitor.on_url(&file.url);
+    }
+}
+
+/// Visits the URL and mimetype of a thumbnail.
+fn visit_thumbnail(
+    visitor: &mut impl MessageVisitor,
+    url: &Option<String>,
+    file: &Option<Box<EncryptedFile>>,
+    info: &Option<Box<ThumbnailInfo>>,
+) {
+    visit_media(visitor, url, file);
+    if let Some(info) = info {
+        visit_opt(&info.mimetype, |mimetype| visitor.on_mimetype(mimetype));
+    }
+}
+
+/// Whether the given string is a syntactically valid BCP 47 language tag, e.g. `en-US`.
+///
+/// This only checks the structure of the tag, not whether its subtags are registered.
+#[cfg(feature = "unstable-pre-spec")]
+fn is_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let valid_primary = match subtags.next() {
+        // Private use and grandfathered tags must have further subtags.
+        Some("x") | Some("X") | Some("i") | Some("I") => tag.len() > 2,
+        Some(primary) => {
+            (2..=8).contains(&primary.len()) && primary.bytes().all(|b| b.is_ascii_alphabetic())
+        }
+        None => false,
+    };
+
+    valid_primary
+        && subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.bytes().all(|b| b.is_ascii_alphanumeric())
+        })
+}
+
+/// Whether the given grapheme cluster is an emoji.
+///
+/// This is a heuristic based on the Unicode blocks that contain emoji and on the emoji
+/// presentation selector and the keycap character, which turn other characters into emoji.
+#[cfg(feature = "unicode")]
+fn is_emoji_grapheme(grapheme: &str) -> bool {
+    let mut chars = grapheme.chars();
+    match chars.next() {
+        Some(first) => is_pictographic(first) || chars.any(|c| c == '\u{FE0F}' || c == '\u{20E3}'),
+        None => false,
+    }
+}
+
+/// Whether the given character is in one of the Unicode blocks that contain emoji.
+fn is_pictographic(c: char) -> bool {
+    matches!(
+        u32::from(c),
+        0x1F000..=0x1FAFF | 0x2300..=0x23FF | 0x2600..=0x27BF | 0x2B00..=0x2BFF | 0x3030 | 0x303D
+    )
+}
+
+/// Whether the given character is invisible and commonly used to obfuscate text, given the
+/// characters around it.
+///
+/// These are zero-width spaces and joiners, the byte order mark and directional formatting
+/// characters. A zero-width joiner between two emoji is not invisible since it is part of an
+/// emoji sequence.
+fn is_invisible_char(prev: Option<char>, c: char, next: Option<char>) -> bool {
+    if c == '\u{200D}' {
+        let after_emoji = prev.map_or(false, |prev| is_pictographic(prev) || prev == '\u{FE0F}');
+        return !(after_emoji && next.map_or(false, is_pictographic));
+    }
+
+    matches!(
+        c,
+        '\u{180E}'
+            | '\u{200B}'..='\u{200C}'
+            | '\u{202A}'..='\u{202E}'
+            | '\u{2060}'
+            | '\u{2066}'..='\u{2069}'
+            | '\u{FEFF}'
+    )
+}
+
+/// Removes the characters of `text` for which `is_invisible_char` is `true`.
+fn strip_invisible_chars(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    chars
+        .iter()
+        .enumerate()
+        .filter(|&(i, &c)| {
+            let prev = if i > 0 { Some(chars[i - 1]) } else { None };
+            !is_invisible_char(prev, c, chars.get(i + 1).copied())
+        })
+        .map(|(_, &c)| c)
+        .collect()
+}
+
+/// Creates the plain-text rich reply fallback quoting the given body of a message by `sender`.
+///
+/// `body` should not contain the reply fallback of the quoted message.
+fn plain_reply_fallback(sender: &UserId, body: &str) -> String {
+    let mut lines = body.lines();
+    let mut fallback = format!("> <{}> {}\n", sender, lines.next().unwrap_or_default());
+    for line in lines {
+        fallback.push_str("> ");
+        fallback.push_str(line);
+        fallback.push('\n');
+    }
+    fallback.push('\n');
+
+    fallback
+}
+
+/// Strips the plain-text rich reply fallback from the start of `body` if the message is a reply
+/// according to `relates_to`.
+///
+/// The body of any other message is returned unchanged, even if it starts with a quote.
+fn body_without_reply_fallback<'a>(body: &'a str, relates_to: Option<&Relation>) -> &'a str {
+    let is_reply = match relates_to {
+        Some(Relation::Reply { .. }) => true,
+        #[cfg(feature = "unstable-pre-spec")]
+        Some(Relation::Thread(thread)) => thread.in_reply_to.is_some() && !thread.is_falling_back,
+        _ => false,
+    };
+
+    if is_reply {
+        strip_plain_reply_fallback(body)
+    } else {
+        body
+    }
+}
+
+/// Strips the plain-text rich reply fallback from the start of `body`, if there is one.
+fn strip_plain_reply_fallback(body: &str) -> &str {
+    if !body.starts_with("> ") {
+        return body;
+    }
+
+    let mut rest = body;
+    while rest.starts_with('>') {
+        rest = rest.find('\n').map_or("", |i| &rest[i + 1..]);
+    }
+
+    if rest.starts_with('\n') {
+        &rest[1..]
+    } else {
+        rest
+    }
+}
+
+/// Strips the `<mx-reply>` rich reply fallback from the start of `html`, if there is one.
+///
+/// If the fallback is never closed, `html` is returned unchanged.
+fn strip_html_reply_fallback(html: &str) -> &str {
+    const REPLY_START: &str = "<mx-reply>";
+    const REPLY_END: &str = "</mx-reply>";
+
+    if !html.starts_with(REPLY_START) {
+        return html;
+    }
+
+    let mut depth = 0_usize;
+    let mut pos = 0;
+    while let Some(i) = html[pos..].find('<') {
+        pos += i;
+        let rest = &html[pos..];
+        if rest.starts_with(REPLY_START) {
+            depth += 1;
+            pos += REPLY_START.len();
+        } else if rest.starts_with(REPLY_END) {
+            depth -= 1;
+            pos += REPLY_END.len();
+            if depth == 0 {
+                return &html[pos..];
+            }
+        } else {
+            pos += 1;
+        }
+    }
+
+    html
+}
+
+/// The first room ID in a `matrix.to` link in the given HTML.
+fn matrix_to_room_id(html: &str) -> Option<RoomId> {
+    const MATRIX_TO: &str = "https://matrix.to/#/";
+
+    let mut rest = html;
+    while let Some(i) = rest.find(MATRIX_TO) {
+        rest = &rest[i + MATRIX_TO.len()..];
+        let end = rest.find(&['/', '?', '"', '\''][..]).unwrap_or(rest.len());
+        if let Ok(room_id) = RoomId::try_from(percent_decode(&rest[..end]).as_str()) {
+            return Some(room_id);
+        }
+    }
+
+    None
+}
+
+/// Decodes the `%XX` escapes in the given URL component.
+///
+/// Invalid escapes are kept as-is and invalid UTF-8 is replaced by `U+FFFD`.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match (bytes[i], s.get(i + 1..i + 3)) {
+            // `from_str_radix` also accepts a leading sign, so check the digits first.
+            (b'%', Some(hex)) if hex.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                u8::from_str_radix(hex, 16).ok()
+            }
+            _ => None,
+        };
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// The maximum nesting of `<mx-reply>` elements in the given HTML.
+fn html_reply_fallback_depth(html: &str) -> usize {
+    let (mut depth, mut max_depth) = (0_usize, 0_usize);
+    let mut rest = html;
+    while let Some(i) = rest.find('<') {
+        rest = &rest[i + 1..];
+        if rest.starts_with("mx-reply>") {
+            depth += 1;
+            max_depth = max_depth.max(depth);
+        } else if rest.starts_with("/mx-reply>") {
+            depth = depth.saturating_sub(1);
+        }
+    }
+
+    max_depth
+}
+
+/// The maximum nesting of `> ` quotes in the reply fallback of the given plain text body.
+fn plain_reply_fallback_depth(body: &str) -> usize {
+    if !body.starts_with("> ") {
+        return 0;
+    }
+
+    body.lines()
+        .take_while(|line| line.starts_with('>'))
+        .map(|line| {
+            let mut level = 0;
+            let mut rest = line;
+            while rest.starts_with('>') {
+                level += 1;
+                rest = &rest[1..];
+                if rest.starts_with(' ') {
+                    rest = &rest[1..];
+                }
+            }
+            level
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        convert::TryFrom,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use js_int::uint;
+    use matches::assert_matches;
+    #[cfg(feature = "regex")]
+    use regex::Regex;
+    use ruma_common::Raw;
+    use ruma_identifiers::{event_id, room_id, user_id};
+    use serde_json::{
+        from_value as from_json_value, json, to_value as to_json_value, value::to_raw_value,
+    };
+
+    use super::{
+        percent_decode, AudioInfo, AudioMessageEventContent, EmoteMessageEventContent,
+        FileMessageEventContent, FormattedBody, GeoCoordinates, GeoCrs, ImageMessageEventContent,
+        LimitType, LocationMessageEventContent, MessageEventContent, MessageFormat, MessageVisitor,
+        NoticeMessageEventContent, Relation, ServerNoticeMessageEventContent, ServerNoticeType,
+        VideoInfo, VideoMessageEventContent,
+    };
+    #[cfg(feature = "unstable-pre-spec")]
+    use super::{LocationAssetType, Mentions, ThreadReplyBuilder, UrlPreview};
+    use crate::{
+        room::{
+            message::{FileInfo, TextMessageEventContent},
+            relationships::InReplyTo,
+            EncryptedFile, ImageInfo, JsonWebKey, MediaCategory, MediaInfo, ThumbnailInfo,
+        },
+        MessageEvent, Unsigned,
+    };
+
+    #[test]
+    fn serialization() {
+        let ev = MessageEvent {
+            content: MessageEventContent::Audio(AudioMessageEventContent {
+                body: "test".into(),
+                #[cfg(feature = "unstable-pre-spec")]
+                filename: None,
+                info: None,
+                url: Some("http://example.com/audio.mp3".into()),
+                file: None,
+            }),
+            event_id: event_id!("$143273582443PhrSn:example.org"),
+            origin_server_ts: UNIX_EPOCH + Duration::from_millis(10_000),
+            room_id: room_id!("!testroomid:example.org"),
+            sender: user_id!("@user:example.org"),
+            unsigned: Unsigned::default(),
+        };
+
+        assert_eq!(
+            to_json_value(ev).unwrap(),
+            json!({
+                "type": "m.room.message",
+                "event_id": "$143273582443PhrSn:example.org",
+                "origin_server_ts": 10_000,
+                "room_id": "!testroomid:example.org",
+                "sender": "@user:example.org",
+                "content": {
+                    "body": "test",
+                    "msgtype": "m.audio",
+                    "url": "http://example.com/audio.mp3",
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn content_serialization() {
+        let message_event_content = MessageEventContent::Audio(AudioMessageEventContent {
+            body: "test".into(),
+            #[cfg(feature = "unstable-pre-spec")]
+            filename: None,
+            info: None,
+            url: Some("http://example.com/audio.mp3".into()),
+            file: None,
+        });
+
+        assert_eq!(
+            to_json_value(&message_event_content).unwrap(),
+            json!({
+                "body": "test",
+                "msgtype": "m.audio",
+                "url": "http://example.com/audio.mp3"
+            })
+        );
+    }
+
+    #[test]
+    fn formatted_body_serialization() {
+        let message_event_content = MessageEventContent::Text(TextMessageEventContent {
+            formatted: Some(FormattedBody {
+                format: MessageFormat::Html,
+                body: "Hello, <em>World</em>!".into(),
+            }),
+            ..TextMessageEventContent::plain("Hello, World!")
+        });
+
+        assert_eq!(
+            to_json_value(&message_event_content).unwrap(),
+            json!({
+                "body": "Hello, World!",
+                "msgtype": "m.text",
+                "format": "org.matrix.custom.html",
+                "formatted_body": "Hello, <em>World</em>!",
+            })
+        );
+    }
+
+    #[test]
+    fn plain_text_content_serialization() {
+        let message_event_content = MessageEventContent::Text(TextMessageEventContent::plain(
+            "> <@test:example.com> test\n\ntest reply",
+        ));
+
+        assert_eq!(
+            to_json_value(&message_event_content).unwrap(),
+            json!({
+                "body": "> <@test:example.com> test\n\ntest reply",
+                "msgtype": "m.text"
+            })
+        );
+    }
+
+    #[test]
+    fn relates_to_content_serialization() {
+        let message_event_content = MessageEventContent::Text(TextMessageEventContent {
+            relates_to: Some(Relation::Reply {
+                in_reply_to: InReplyTo::new(event_id!("$15827405538098VGFWH:example.com")),
+                custom: BTreeMap::new(),
+            }),
+            ..TextMessageEventContent::plain("> <@test:example.com> test\n\ntest reply")
+        });
+
+        let json_data = json!({
+            "body": "> <@test:example.com> test\n\ntest reply",
+            "msgtype": "m.text",
+            "m.relates_to": {
+                "m.in_reply_to": {
+                    "event_id": "$15827405538098VGFWH:example.com"
+                }
+            }
+        });
+
+        assert_eq!(to_json_value(&message_event_content).unwrap(), json_data);
+    }
+
+    #[test]
+    #[cfg(not(feature = "unstable-pre-spec"))]
+    fn edit_deserialization() {
+        let json_data = json!({
+            "body": "test",
+            "msgtype": "m.text",
+            "m.relates_to": {
+                "rel_type": "m.replace",
+                "event_id": event_id!("$1598361704261elfgc:localhost"),
+            }
+        });
+
+        assert_matches!(
+            from_json_value::<MessageEventContent>(json_data).unwrap(),
+            MessageEventContent::Text(TextMessageEventContent {
+                body,
+                formatted: None,
+                relates_to: Some(Relation::Custom(_)),
+                ..
+            }) if body == "test"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn root_level_replacement_deserialization() {
+        // MSC2676 puts `rel_type` and `event_id` of an edit at the root of `m.relates_to`:
+        // https://github.com/matrix-org/matrix-doc/pull/2676
+        let json_data = json!({
+            "body": "* test",
+            "msgtype": "m.text",
+            "m.new_content": {
+                "body": "test",
+                "msgtype": "m.text",
+            },
+            "m.relates_to": {
+                "rel_type": "m.replace",
+                "event_id": "$1598361704261elfgc:localhost",
+            }
+        });
+
+        let content = from_json_value::<MessageEventContent>(json_data).unwrap();
+        assert_matches!(
+            content,
+            MessageEventContent::Text(TextMessageEventContent {
+                relates_to: Some(Relation::Replacement(replacement)),
+                new_content: Some(new_content),
+                ..
+            }) if replacement.event_id == event_id!("$1598361704261elfgc:localhost")
+                && matches!(*new_content, MessageEventContent::Text(_))
+        );
+    }
+
+    #[test]
+    fn content_deserialization() {
+        let json_data = json!({
+            "body": "test",
+            "msgtype": "m.audio",
+            "url": "http://example.com/audio.mp3"
+        });
+
+        assert_matches!(
+            from_json_value::<Raw<MessageEventContent>>(json_data)
+                .unwrap()
+                .deserialize()
+                .unwrap(),
+            MessageEventContent::Audio(AudioMessageEventContent {
+                body,
+                info: None,
+                url: Some(url),
+                file: None,
+                ..
+            }) if body == "test" && url == "http://example.com/audio.mp3"
+        );
+    }
+
+    #[test]
+    fn content_deserialization_failure() {
+        let json_data = json!({
+            "body": "test","msgtype": "m.location",
+            "url": "http://example.com/audio.mp3"
+        });
+        assert!(from_json_value::<Raw<MessageEventContent>>(json_data)
+            .unwrap()
+            .deserialize()
+            .is_err());
+    }
+
+    #[test]
+    fn string_enums_from_str() {
+        assert_eq!("org.matrix.custom.html".parse::<MessageFormat>(), Ok(MessageFormat::Html));
+        assert_eq!(
+            "org.example.markdown".parse::<MessageFormat>().unwrap().as_str(),
+            "org.example.markdown"
+        );
+        assert_eq!(
+            "m.server_notice.usage_limit_reached".parse::<ServerNoticeType>(),
+            Ok(ServerNoticeType::UsageLimitReached)
+        );
+        assert_eq!("monthly_active_user".parse::<LimitType>(), Ok(LimitType::MonthlyActiveUser));
+    }
+
+    fn video_info(size: u32, duration: u32) -> VideoInfo {
+        VideoInfo {
+            duration: Some(duration.into()),
+            height: None,
+            width: None,
+            mimetype: Some("video/mp4".into()),
+            size: Some(size.into()),
+            thumbnail_info: None,
+            thumbnail_url: None,
+            thumbnail_file: None,
+            #[cfg(feature = "unstable-pre-spec")]
+            blurhash: None,
+        }
+    }
+
+    #[test]
+    fn video_info_exceeds_size() {
+        let info = video_info(20_000_000, 1_000);
+        assert!(info.exceeds(uint!(10_000_000), uint!(60_000)));
+    }
+
+    #[test]
+    fn video_info_within_limits() {
+        let info = video_info(1_000_000, 1_000);
+        assert!(!info.exceeds(uint!(10_000_000), uint!(60_000)));
+    }
+
+    #[test]
+    fn canonical_json_ignores_key_order() {
+        let first: MessageEventContent = serde_json::from_str(
+            r#"{"msgtype":"m.text","formatted_body":"<b>hi</b>","body":"hi","format":"org.matrix.custom.html"}"#,
+        )
+        .unwrap();
+        let second: MessageEventContent = serde_json::from_str(
+            r#"{ "body": "hi", "format": "org.matrix.custom.html", "msgtype": "m.text", "formatted_body": "<b>hi</b>" }"#,
+        )
+        .unwrap();
+
+        let canonical = first.to_canonical_json().unwrap();
+        assert_eq!(canonical, second.to_canonical_json().unwrap());
+        assert_eq!(
+            canonical,
+            r#"{"body":"hi","format":"org.matrix.custom.html","formatted_body":"<b>hi</b>","msgtype":"m.text"}"#
+        );
+    }
+
+    #[test]
+    fn image_with_info() {
+        let content = ImageMessageEventContent {
+            body: "sunset.png".into(),
+            #[cfg(feature = "unstable-pre-spec")]
+            filename: None,
+            #[cfg(feature = "unstable-pre-spec")]
+            sticker_like: false,
+            info: None,
+            url: Some("mxc://example.org/sunset".into()),
+            file: None,
+        }
+        .with_info(ImageInfo {
+            height: Some(uint!(480)),
+            width: Some(uint!(640)),
+            mimetype: Some("image/png".into()),
+            size: None,
+            thumbnail_info: None,
+            thumbnail_url: None,
+            thumbnail_file: None,
+            #[cfg(feature = "unstable-pre-spec")]
+            blurhash: None,
+        });
+
+        assert_eq!(
+            to_json_value(MessageEventContent::Image(content)).unwrap(),
+            json!({
+                "body": "sunset.png",
+                "msgtype": "m.image",
+                "url": "mxc://example.org/sunset",
+                "info": {
+                    "h": 480,
+                    "w": 640,
+                    "mimetype": "image/png",
+                },
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "compat")]
+    fn thumbnail_url_aliases() {
+        use super::FileInfo;
+
+        for key in &["thumbnail_url", "thumb_url", "thumbnailUrl"] {
+            let info: FileInfo =
+                from_json_value(json!({ *key: "mxc://example.org/thumbnail" })).unwrap();
+            assert_eq!(info.thumbnail_url.as_deref(), Some("mxc://example.org/thumbnail"));
+            assert_eq!(
+                to_json_value(&info).unwrap(),
+                json!({ "thumbnail_url": "mxc://example.org/thumbnail" })
+            );
+        }
+    }
+
+    /// Makes `content` a reply to `$original:example.org`.
+    fn as_reply(content: TextMessageEventContent) -> TextMessageEventContent {
+        TextMessageEventContent {
+            relates_to: Some(Relation::Reply {
+                in_reply_to: InReplyTo::new(event_id!("$original:example.org")),
+                custom: BTreeMap::new(),
+            }),
+            ..content
+        }
+    }
+
+    #[test]
+    fn preview_long_text() {
+        let content = MessageEventContent::Text(as_reply(TextMessageEventContent::plain(
+            "> <@alice:example.org> Where are you?\n\nOn my way,\n  be there in five minutes",
+        )));
+
+        assert_eq!(content.preview(100), "On my way, be there in five minutes");
+        assert_eq!(content.preview(10), "On my way…");
+    }
+
+    #[test]
+    fn preview_server_notice_reply() {
+        let content = MessageEventContent::ServerNotice(ServerNoticeMessageEventContent {
+            relates_to: Some(Relation::Reply {
+                in_reply_to: InReplyTo::new(event_id!("$original:example.org")),
+                custom: BTreeMap::new(),
+            }),
+            ..ServerNoticeMessageEventContent::custom(
+                "> <@alice:example.org> Is the server down?
+
+Maintenance is over",
+                "org.example.maintenance",
+            )
+        });
+
+        assert_eq!(content.preview(100), "Maintenance is over");
+    }
+
+    #[test]
+    fn quote_without_reply_is_kept() {
+        let content = MessageEventContent::text_plain("> quote");
+
+        assert_eq!(content.preview(100), "> quote");
+        assert_eq!(content.search_text(), "> quote");
+        assert_eq!(content.accessibility_label(), "> quote");
+        assert_eq!(content.estimated_line_count(80), 1);
+        assert_eq!(content.displayed_content().body(), "> quote");
+
+        #[cfg(feature = "unicode")]
+        assert_eq!(TextMessageEventContent::plain("> quote").grapheme_len(), 7);
+    }
+
+    #[test]
+    fn preview_image_without_body() {
+        let content = MessageEventContent::Image(ImageMessageEventContent {
+            body: "".into(),
+            #[cfg(feature = "unstable-pre-spec")]
+            filename: None,
+            #[cfg(feature = "unstable-pre-spec")]
+            sticker_like: false,
+            info: None,
+            url: Some("mxc://example.org/image".into()),
+            file: None,
+        });
+
+        assert_eq!(content.preview(50), "📷 Image");
+    }
+
+    #[test]
+    fn preview_location() {
+        let content = MessageEventContent::Location(LocationMessageEventContent::new(
+            "Big Ben, London, UK",
+            "geo:51.5008,0.1247",
+        ));
+
+        assert_eq!(content.preview(50), "Big Ben, London, UK");
+    }
+
+    #[test]
+    fn emote_constructors() {
+        assert_matches!(
+            EmoteMessageEventContent::plain("waves"),
+            EmoteMessageEventContent { body, formatted: None } if body == "waves"
+        );
+        assert_matches!(
+            EmoteMessageEventContent::html("waves", "<em>waves</em>"),
+            EmoteMessageEventContent {
+                body,
+                formatted: Some(FormattedBody { format: MessageFormat::Html, body: html_body }),
+            } if body == "waves" && html_body == "<em>waves</em>"
+        );
+    }
+
+    #[test]
+    fn plain_emote_serialization() {
+        assert_eq!(
+            to_json_value(MessageEventContent::emote_plain("waves")).unwrap(),
+            json!({
+                "body": "waves",
+                "msgtype": "m.emote",
+            })
+        );
+    }
+
+    #[test]
+    fn html_emote_serialization() {
+        assert_eq!(
+            to_json_value(MessageEventContent::emote_html("waves", "<em>waves</em>")).unwrap(),
+            json!({
+                "body": "waves",
+                "format": "org.matrix.custom.html",
+                "formatted_body": "<em>waves</em>",
+                "msgtype": "m.emote",
+            })
+        );
+    }
+
+    #[test]
+    fn geo_uri_with_crs_and_uncertainty() {
+        assert_eq!(
+            "geo:51.5008,0.1247;crs=wgs84;u=10".parse::<GeoCoordinates>(),
+            Ok(GeoCoordinates {
+                latitude: 51.5008,
+                longitude: 0.1247,
+                altitude: None,
+                crs: GeoCrs::Wgs84,
+                uncertainty: Some(10.0),
+            })
+        );
+
+        let custom = "geo:51.5008,0.1247,20;crs=Moon-2011".parse::<GeoCoordinates>().unwrap();
+        assert_eq!(custom.altitude, Some(20.0));
+        assert_eq!(custom.crs.as_ref(), "moon-2011");
+    }
+
+    #[test]
+    fn geo_uri_with_valueless_parameter() {
+        assert_eq!(
+            "geo:51.5008,0.1247;u=10;foo;bar=baz".parse::<GeoCoordinates>(),
+            Ok(GeoCoordinates {
+                latitude: 51.5008,
+                longitude: 0.1247,
+                altitude: None,
+                crs: GeoCrs::Wgs84,
+                uncertainty: Some(10.0),
+            })
+        );
+        assert!("geo:51.5008,0.1247;u".parse::<GeoCoordinates>().is_err());
+        assert!("geo:51.5008,0.1247;crs".parse::<GeoCoordinates>().is_err());
+        assert!("geo:51.5008,0.1247;".parse::<GeoCoordinates>().is_err());
+    }
+
+    #[test]
+    fn geo_uri_without_parameters() {
+        let content = LocationMessageEventContent::new("Big Ben, London, UK", "geo:51.5008,0.1247");
+
+        assert_eq!(
+            content.coordinates(),
+            Ok(GeoCoordinates {
+                latitude: 51.5008,
+                longitude: 0.1247,
+                altitude: None,
+                crs: GeoCrs::Wgs84,
+                uncertainty: None,
+            })
+        );
+        assert!("51.5008,0.1247".parse::<GeoCoordinates>().is_err());
+    }
+
+    #[test]
+    fn geo_uri_coordinate_ranges() {
+        let valid = LocationMessageEventContent::new("Antipode", "geo:-90,180");
+        assert_eq!(valid.validate_coordinates(), Ok(()));
+
+        let invalid = LocationMessageEventContent::new("Nowhere", "geo:90.5,0.1247");
+        assert!(invalid.validate_coordinates().is_err());
+        assert!("geo:51.5008,-180.1".parse::<GeoCoordinates>().is_err());
+        assert!("geo:NaN,0".parse::<GeoCoordinates>().is_err());
+    }
+
+    #[test]
+    fn geo_uri_number_syntax() {
+        assert!("geo:0,inf".parse::<GeoCoordinates>().is_err());
+        assert!("geo:5e1,0".parse::<GeoCoordinates>().is_err());
+        assert!("geo:+51.5,0".parse::<GeoCoordinates>().is_err());
+        assert!("geo:51.,0".parse::<GeoCoordinates>().is_err());
+        assert!("geo:.5,0".parse::<GeoCoordinates>().is_err());
+        assert!("geo:51.5,0;u=-10".parse::<GeoCoordinates>().is_err());
+        assert!("geo:51.5,0;u=NaN".parse::<GeoCoordinates>().is_err());
+        assert_eq!("geo:-51.5,-0.1,-20".parse::<GeoCoordinates>().unwrap().altitude, Some(-20.0));
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn reference_relation_serialization() {
+        assert_eq!(
+            to_json_value(Relation::reference(event_id!("$poll:example.org"))).unwrap(),
+            json!({
+                "rel_type": "m.reference",
+                "event_id": "$poll:example.org",
+            })
+        );
+    }
+
+    #[test]
+    fn info_serialized_len() {
+        let info = ImageInfo {
+            height: Some(uint!(480)),
+            width: Some(uint!(640)),
+            mimetype: Some("image/png".into()),
+            size: None,
+            thumbnail_info: None,
+            thumbnail_url: None,
+            thumbnail_file: None,
+            #[cfg(feature = "unstable-pre-spec")]
+            blurhash: None,
+        };
+        assert_eq!(info.serialized_len(), r#"{"h":480,"w":640,"mimetype":"image/png"}"#.len());
+
+        let info = video_info(1_000_000, 1_000);
+        assert_eq!(info.serialized_len(), serde_json::to_string(&info).unwrap().len());
+    }
+
+    #[test]
+    fn format_without_formatted_body() {
+        let json_data = json!({
+            "body": "test",
+            "msgtype": "m.text",
+            "format": "org.matrix.custom.html",
+        });
+
+        assert_matches!(
+            from_json_value::<MessageEventContent>(json_data).unwrap(),
+            MessageEventContent::Text(TextMessageEventContent {
+                body,
+                formatted: None,
+                relates_to: None,
+                ..
+            }) if body == "test"
+        );
+    }
+
+    #[test]
+    fn formatted_body_without_format() {
+        let json_data = json!({
+            "body": "test",
+            "msgtype": "m.notice",
+            "formatted_body": "<b>test</b>",
+        });
+
+        assert_matches!(
+            from_json_value::<MessageEventContent>(json_data).unwrap(),
+            MessageEventContent::Notice(NoticeMessageEventContent {
+                body,
+                formatted: None,
+                relates_to: None,
+                ..
+            }) if body == "test"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn from_extensible_text_with_html() {
+        let content = serde_json::value::to_raw_value(&json!({
+            "m.message": [
+                { "body": "<b>Hello</b>, World!", "mimetype": "text/html" },
+                { "body": "Hello, World!" },
+            ]
+        }))
+        .unwrap();
+
+        assert_matches!(
+            MessageEventContent::from_extensible("m.message", &content).unwrap(),
+            MessageEventContent::Text(TextMessageEventContent {
+                body,
+                formatted: Some(FormattedBody { format: MessageFormat::Html, body: html_body }),
+                relates_to: None,
+                ..
+            }) if body == "Hello, World!" && html_body == "<b>Hello</b>, World!"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn from_extensible_html_only() {
+        let content = serde_json::value::to_raw_value(&json!({
+            "m.html": "<b>Hello</b>, World!",
+        }))
+        .unwrap();
+
+        assert!(MessageEventContent::from_extensible("m.message", &content).is_err());
+    }
+
+    #[test]
+    fn is_any_notice() {
+        assert!(MessageEventContent::notice_plain("Beep boop").is_any_notice());
+        assert!(MessageEventContent::ServerNotice(ServerNoticeMessageEventContent {
+            body: "Usage limit reached".into(),
+            server_notice_type: ServerNoticeType::UsageLimitReached,
+            admin_contact: None,
+            limit_type: Some(LimitType::MonthlyActiveUser),
+            relates_to: None,
+        })
+        .is_any_notice());
+        assert!(!MessageEventContent::text_plain("Hello").is_any_notice());
+    }
+
+    #[test]
+    #[cfg(feature = "html")]
+    fn formatted_body_nesting_depth() {
+        let flat = FormattedBody::html("Hello, <b>World</b>!<br>");
+        assert_eq!(flat.max_nesting_depth(), 1);
+
+        let nested = FormattedBody::html(
+            "<blockquote><p>Quote with <em>a <strong>nested</strong> list</em></p>\
+             <ul><li>item<br/></li></ul></blockquote>",
+        );
+        assert_eq!(nested.max_nesting_depth(), 4);
+        assert!(nested.validate_depth(4).is_ok());
+
+        let too_deep = FormattedBody::html("<div><div><div><div>deep</div></div></div></div>");
+        assert_eq!(too_deep.max_nesting_depth(), 4);
+        assert!(too_deep.validate_depth(3).is_err());
+    }
+
+    fn encrypted_file() -> EncryptedFile {
+        EncryptedFile {
+            url: "mxc://example.org/encrypted".into(),
+            key: JsonWebKey {
+                kty: "oct".into(),
+                key_ops: vec!["encrypt".into(), "decrypt".into()],
+                alg: "A256CTR".into(),
+                k: "TLlG_OpX807zzQuuwv4QZGJ21_u7weemFGYJFszMn9A".into(),
+                ext: true,
+            },
+            iv: "S22dq3NAX8wAAAAAAAAAAA".into(),
+            hashes: vec![("sha256".into(), "aWOHudBnDkJ9IwaR1Nd8XKoI7DOrqDTwt6xDPfVGN6Q".into())]
+                .into_iter()
+                .collect(),
+            v: "v2".into(),
+        }
+    }
+
+    #[test]
+    fn encrypted_media_constructors() {
+        let audio = AudioMessageEventContent::encrypted("audio.ogg", encrypted_file(), None);
+        assert!(audio.url.is_none());
+        assert_eq!(audio.file.unwrap().url, "mxc://example.org/encrypted");
+
+        let file = FileMessageEventContent::encrypted("file.pdf", encrypted_file(), None);
+        assert!(file.url.is_none());
+        assert_eq!(file.file.unwrap().url, "mxc://example.org/encrypted");
+
+        let image = ImageMessageEventContent::encrypted("image.png", encrypted_file(), None);
+        assert!(image.url.is_none());
+        assert_eq!(image.file.unwrap().url, "mxc://example.org/encrypted");
+
+        let video = VideoMessageEventContent::encrypted(
+            "video.mp4",
+            encrypted_file(),
+            Some(video_info(1_000, 1_000)),
+        );
+        assert!(video.url.is_none());
+        assert!(video.info.is_some());
+        assert_eq!(video.file.unwrap().url, "mxc://example.org/encrypted");
+    }
+
+    #[test]
+    #[cfg(feature = "html")]
+    fn formatted_body_code_blocks() {
+        let formatted = FormattedBody::html(
+            "<p>Use <code>cargo</code>:</p>\
+             <pre><code class=\"language-rust\">fn main() {\n    println!(&quot;hi&quot;);\n}</code></pre>\
+             <pre><code>$ cargo run</code></pre>",
+        );
+
+        assert_eq!(
+            formatted.code_blocks(),
+            vec![
+                (Some("rust".to_owned()), "fn main() {\n    println!(\"hi\");\n}".to_owned()),
+                (None, "$ cargo run".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "hashing")]
+    fn content_hash() {
+        let first = MessageEventContent::text_html("Hello", "<b>Hello</b>");
+        let second: MessageEventContent = from_json_value(json!({
+            "formatted_body": "<b>Hello</b>",
+            "format": "org.matrix.custom.html",
+            "msgtype": "m.text",
+            "body": "Hello",
+        }))
+        .unwrap();
+        let different = MessageEventContent::text_html("Hello!", "<b>Hello</b>!");
+
+        assert_eq!(first.content_hash().unwrap(), second.content_hash().unwrap());
+        assert_ne!(first.content_hash().unwrap(), different.content_hash().unwrap());
+
+        let reply = MessageEventContent::Text(TextMessageEventContent {
+            relates_to: Some(Relation::Reply {
+                in_reply_to: InReplyTo::new(event_id!("$15827405538098VGFWH:example.com")),
+                custom: BTreeMap::new(),
+            }),
+            ..TextMessageEventContent::html("Hello", "<b>Hello</b>")
+        });
+        assert_ne!(first.content_hash().unwrap(), reply.content_hash().unwrap());
+        assert_eq!(
+            first.content_hash_without_relation().unwrap(),
+            reply.content_hash_without_relation().unwrap()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "hashing")]
+    fn content_hash_of_non_canonical_relation() {
+        let content = MessageEventContent::Text(TextMessageEventContent {
+            relates_to: Some(Relation::Custom(
+                json!({ "rel_type": "org.example.score", "score": 0.5 }),
+            )),
+            ..TextMessageEventContent::plain("Hello")
+        });
+
+        assert!(content.content_hash().is_err());
+        assert!(content.content_hash_without_relation().is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn formatting_only_edit() {
+        let edit = TextMessageEventContent::html(
+            "Check out example.org",
+            "Check out <a href=\"https://example.org\">example.org</a>",
+        )
+        .make_replacement(event_id!("$original:example.org"));
+
+        assert_eq!(
+            to_json_value(MessageEventContent::Text(edit)).unwrap(),
+            json!({
+                "msgtype": "m.text",
+                "body": "* Check out example.org",
+                "format": "org.matrix.custom.html",
+                "formatted_body": "* Check out <a href=\"https://example.org\">example.org</a>",
+                "m.new_content": {
+                    "msgtype": "m.text",
+                    "body": "Check out example.org",
+                    "format": "org.matrix.custom.html",
+                    "formatted_body": "Check out <a href=\"https://example.org\">example.org</a>",
+                },
+                "m.relates_to": {
+                    "rel_type": "m.replace",
+                    "event_id": "$original:example.org",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn append_formatted_text() {
+        let mut first = TextMessageEventContent::html("Hello", "<b>Hello</b>");
+        first.relates_to = Some(Relation::Reply {
+            in_reply_to: InReplyTo::new(event_id!("$15827405538098VGFWH:example.com")),
+            custom: BTreeMap::new(),
+        });
+        let second = TextMessageEventContent::html("World", "<i>World</i>");
+
+        first.append(&second);
+
+        assert_eq!(
+            to_json_value(MessageEventContent::Text(first)).unwrap(),
+            json!({
+                "msgtype": "m.text",
+                "body": "Hello\nWorld",
+                "format": "org.matrix.custom.html",
+                "formatted_body": "<b>Hello</b><br><i>World</i>",
+            })
+        );
+    }
+
+    #[test]
+    fn search_text() {
+        let file = MessageEventContent::File(FileMessageEventContent {
+            body: "Quarterly report".into(),
+            filename: Some("report-q3.pdf".into()),
+            info: None,
+            url: Some("mxc://example.org/abc".into()),
+            file: None,
+        });
+        assert_eq!(file.search_text(), "Quarterly report\nreport-q3.pdf");
+
+        let reply = MessageEventContent::Text(as_reply(TextMessageEventContent::plain(
+            "> <@alice:example.org> What time?\n> Tell me\n\nAt noon",
+        )));
+        assert_eq!(reply.search_text(), "At noon");
+    }
+
+    #[test]
+    fn relates_to() {
+        let reply = MessageEventContent::Text(TextMessageEventContent {
+            relates_to: Some(Relation::Reply {
+                in_reply_to: InReplyTo::new(event_id!("$15827405538098VGFWH:example.com")),
+                custom: BTreeMap::new(),
+            }),
+            ..TextMessageEventContent::plain("> <@test:example.com> test\n\ntest reply")
+        });
+        assert_matches!(
+            reply.relates_to(),
+            Some(Relation::Reply { in_reply_to, .. })
+            if in_reply_to.event_id == event_id!("$15827405538098VGFWH:example.com")
+        );
+
+        let audio = MessageEventContent::Audio(AudioMessageEventContent {
+            body: "Upload: my_song.mp3".into(),
+            #[cfg(feature = "unstable-pre-spec")]
+            filename: None,
+            info: None,
+            url: Some("mxc://notareal.hs/file".into()),
+            file: None,
+        });
+        assert!(audio.relates_to().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "unicode")]
+    fn grapheme_len() {
+        // Family emoji built from four emoji joined by ZWJs.
+        let family = TextMessageEventContent::plain("👨\u{200d}👩\u{200d}👧\u{200d}👦!");
+        assert_eq!(family.grapheme_len(), 2);
+
+        // "e" followed by a combining acute accent.
+        let combining = TextMessageEventContent::plain("cafe\u{301}");
+        assert_eq!(combining.grapheme_len(), 4);
+
+        let reply = as_reply(TextMessageEventContent::plain("> <@test:example.com> test\n\nok"));
+        assert_eq!(reply.grapheme_len(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "unicode")]
+    fn emojis_flag() {
+        let content = TextMessageEventContent::plain("Greetings from 🇫🇷 and 🇫🇷! 👋");
+        assert_eq!(content.emojis(), vec!["🇫🇷", "🇫🇷", "👋"]);
+    }
+
+    #[test]
+    #[cfg(feature = "unicode")]
+    fn emojis_zwj_family() {
+        let content = as_reply(TextMessageEventContent::plain(
+            "> <@test:example.com> 🎉\n\nThe 👨\u{200d}👩\u{200d}👧\u{200d}👦 says hi ❤\u{fe0f}",
+        ));
+        assert_eq!(content.emojis(), vec!["👨\u{200d}👩\u{200d}👧\u{200d}👦", "❤\u{fe0f}"]);
+
+        assert!(TextMessageEventContent::plain("No emoji here, 123 #").emojis().is_empty());
+    }
+
+    #[test]
+    fn with_relation() {
+        let reply = Relation::Reply {
+            in_reply_to: InReplyTo::new(event_id!("$15827405538098VGFWH:example.com")),
+            custom: BTreeMap::new(),
+        };
+
+        let content = MessageEventContent::text_plain("test reply").with_relation(Some(reply));
+        assert_matches!(content.relates_to(), Some(Relation::Reply { .. }));
+
+        let content = content.with_relation(None);
+        assert!(content.relates_to().is_none());
+    }
+
+    #[test]
+    fn media_default_body() {
+        let image = ImageMessageEventContent::with_default_body(
+            "mxc://example.org/image",
+            Some(ImageInfo {
+                height: None,
+                width: None,
+                mimetype: Some("image/png".into()),
+                size: None,
+                thumbnail_info: None,
+                thumbnail_url: None,
+                thumbnail_file: None,
+                #[cfg(feature = "unstable-pre-spec")]
+                blurhash: None,
+            }),
+        );
+        assert_eq!(image.body, "Image.png");
+
+        let video = VideoMessageEventContent::with_default_body("mxc://example.org/video", None);
+        assert_eq!(video.body, "Video");
+
+        let file = FileMessageEventContent::with_default_body(
+            "mxc://example.org/file",
+            Some("report.pdf".into()),
+            None,
+        );
+        assert_eq!(file.body, "report.pdf");
+    }
+
+    #[test]
+    fn media_urls() {
+        let video = MessageEventContent::Video(VideoMessageEventContent {
+            body: "clip.mp4".into(),
+            #[cfg(feature = "unstable-pre-spec")]
+            filename: None,
+            info: Some(Box::new(VideoInfo {
+                thumbnail_url: Some("mxc://example.org/thumbnail".into()),
+                ..video_info(1024, 1000)
+            })),
+            url: Some("mxc://example.org/video".into()),
+            file: None,
+        });
+        assert_eq!(
+            video.media_urls(),
+            vec!["mxc://example.org/video", "mxc://example.org/thumbnail"]
+        );
+
+        assert!(MessageEventContent::text_plain("Hello").media_urls().is_empty());
+    }
+
+    #[test]
+    fn from_slice() {
+        let bytes = br#"{"body":"Hello","msgtype":"m.notice"}"#;
+
+        assert_matches!(
+            MessageEventContent::from_slice(bytes).unwrap(),
+            MessageEventContent::Notice(NoticeMessageEventContent { body, .. }) if body == "Hello"
+        );
+        assert!(MessageEventContent::from_slice(b"{\"body\":").is_err());
+    }
+
+    #[test]
+    fn same_target_and_kind() {
+        let reply_to = |event_id| Relation::Reply {
+            in_reply_to: InReplyTo::new(event_id),
+            custom: BTreeMap::new(),
+        };
+        let first = reply_to(event_id!("$first:example.com"));
+
+        let mut custom = BTreeMap::new();
+        custom.insert("com.example.x".to_owned(), json!(true));
+        let first_with_extra_keys = Relation::Reply {
+            in_reply_to: InReplyTo::new(event_id!("$first:example.com")),
+            custom,
+        };
+
+        assert!(first.same_target_and_kind(&first_with_extra_keys));
+        assert!(!first.same_target_and_kind(&reply_to(event_id!("$second:example.com"))));
+    }
+
+    #[test]
+    fn from_lossy_utf8() {
+        let bytes = b"{\"body\":\"caf\xC3 ok\",\"msgtype\":\"m.text\"}";
+        assert!(MessageEventContent::from_slice(bytes).is_err());
+
+        assert_matches!(
+            MessageEventContent::from_lossy_utf8(bytes).unwrap(),
+            MessageEventContent::Text(TextMessageEventContent { body, .. })
+            if body == "caf\u{FFFD} ok"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn location_asset_serialization() {
+        let content = LocationMessageEventContent {
+            asset: Some(LocationAssetType::Pin),
+            ..LocationMessageEventContent::new("Meeting point", "geo:51.5008,0.1247")
+        };
+
+        assert_eq!(
+            to_json_value(MessageEventContent::Location(content)).unwrap(),
+            json!({
+                "body": "Meeting point",
+                "geo_uri": "geo:51.5008,0.1247",
+                "msgtype": "m.location",
+                "m.asset": { "type": "m.pin" },
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn location_asset_default() {
+        let content = from_json_value::<LocationMessageEventContent>(json!({
+            "body": "Big Ben, London, UK",
+            "geo_uri": "geo:51.5008,0.1247",
+        }))
+        .unwrap();
+
+        assert_eq!(content.asset, None);
+        assert_eq!(content.asset_type(), LocationAssetType::Self_);
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn location_timestamp() {
+        let content = LocationMessageEventContent {
+            ts: Some(js_int::UInt::try_from(1_636_829_458_000_u64).unwrap()),
+            ..LocationMessageEventContent::new("Big Ben, London, UK", "geo:51.5008,0.1247")
+        };
+
+        assert_eq!(
+            to_json_value(&content).unwrap(),
+            json!({
+                "body": "Big Ben, London, UK",
+                "geo_uri": "geo:51.5008,0.1247",
+                "m.ts": 1_636_829_458_000_u64,
+            })
+        );
+        assert_eq!(
+            content.timestamp(),
+            Some(UNIX_EPOCH + Duration::from_millis(1_636_829_458_000))
+        );
+    }
+
+    #[test]
+    fn is_media() {
+        let audio = MessageEventContent::Audio(AudioMessageEventContent::with_default_body(
+            "mxc://example.org/audio",
+            None,
+        ));
+        let file = MessageEventContent::File(FileMessageEventContent::with_default_body(
+            "mxc://example.org/file",
+            None,
+            None,
+        ));
+        let image = MessageEventContent::Image(ImageMessageEventContent::with_default_body(
+            "mxc://example.org/image",
+            None,
+        ));
+        let video = MessageEventContent::Video(VideoMessageEventContent::with_default_body(
+            "mxc://example.org/video",
+            None,
+        ));
+
+        assert!(audio.is_media());
+        assert!(file.is_media());
+        assert!(image.is_media());
+        assert!(video.is_media());
+        assert!(!MessageEventContent::text_plain("Hello").is_media());
+    }
+
+    #[test]
+    fn set_body_preserving_fallback() {
+        let mut content = as_reply(TextMessageEventContent::html(
+            "> <@alice:example.org> Lunch?\n\nSure",
+            "<mx-reply><blockquote>Lunch?</blockquote></mx-reply>Sure",
+        ));
+
+        content.set_body_preserving_fallback("Sure, at <noon>");
+
+        assert_eq!(content.body, "> <@alice:example.org> Lunch?\n\nSure, at <noon>");
+        assert_eq!(
+            content.formatted.unwrap().body,
+            "<mx-reply><blockquote>Lunch?</blockquote></mx-reply>Sure, at &lt;noon&gt;"
+        );
+    }
+
+    #[test]
+    fn payload_into_content() {
+        let audio: MessageEventContent =
+            AudioMessageEventContent::with_default_body("mxc://example.org/audio", None).into();
+        assert_matches!(audio, MessageEventContent::Audio(_));
+
+        let emote: MessageEventContent = EmoteMessageEventContent::plain("waves").into();
+        assert_matches!(emote, MessageEventContent::Emote(_));
+
+        let file: MessageEventContent =
+            FileMessageEventContent::with_default_body("mxc://example.org/file", None, None).into();
+        assert_matches!(file, MessageEventContent::File(_));
+
+        let image: MessageEventContent =
+            ImageMessageEventContent::with_default_body("mxc://example.org/image", None).into();
+        assert_matches!(image, MessageEventContent::Image(_));
+
+        let location: MessageEventContent =
+            LocationMessageEventContent::new("Big Ben, London, UK", "geo:51.5008,0.1247").into();
+        assert_matches!(location, MessageEventContent::Location(_));
+
+        let notice: MessageEventContent = NoticeMessageEventContent::plain("Beep boop").into();
+        assert_matches!(notice, MessageEventContent::Notice(_));
+
+        let server_notice: MessageEventContent = ServerNoticeMessageEventContent {
+            body: "Usage limit reached".into(),
+            server_notice_type: ServerNoticeType::UsageLimitReached,
+            admin_contact: None,
+            limit_type: Some(LimitType::MonthlyActiveUser),
+            relates_to: None,
+        }
+        .into();
+        assert_matches!(server_notice, MessageEventContent::ServerNotice(_));
+
+        let text: MessageEventContent = TextMessageEventContent::plain("Hello").into();
+        assert_matches!(text, MessageEventContent::Text(_));
+
+        let video: MessageEventContent =
+            VideoMessageEventContent::with_default_body("mxc://example.org/video", None).into();
+        assert_matches!(video, MessageEventContent::Video(_));
+    }
+
+    #[test]
+    fn prune_empty_info() {
+        let mut content = MessageEventContent::Image(ImageMessageEventContent {
+            info: Some(Box::new(ImageInfo {
+                height: None,
+                width: None,
+                mimetype: None,
+                size: None,
+                thumbnail_info: None,
+                thumbnail_url: None,
+                thumbnail_file: None,
+                #[cfg(feature = "unstable-pre-spec")]
+                blurhash: None,
+            })),
+            ..ImageMessageEventContent::with_default_body("mxc://example.org/image", None)
+        });
+        content.prune();
+
+        assert_eq!(
+            to_json_value(&content).unwrap(),
+            json!({
+                "body": "Image",
+                "url": "mxc://example.org/image",
+                "msgtype": "m.image",
+            })
+        );
+    }
+
+    #[test]
+    fn accessibility_label() {
+        let image = MessageEventContent::Image(ImageMessageEventContent {
+            body: "sunset.png".into(),
+            ..ImageMessageEventContent::with_default_body("mxc://example.org/image", None)
+        });
+        assert_eq!(image.accessibility_label(), "Image: sunset.png");
+
+        let voice = MessageEventContent::Audio(AudioMessageEventContent {
+            body: "Voice message".into(),
+            #[cfg(feature = "unstable-pre-spec")]
+            filename: None,
+            info: Some(Box::new(AudioInfo {
+                duration: Some(uint!(12_345)),
+                mimetype: Some("audio/ogg".into()),
+                size: None,
+            })),
+            url: Some("mxc://example.org/voice".into()),
+            file: None,
+        });
+        assert_eq!(voice.accessibility_label(), "Audio: Voice message, 12 seconds");
+
+        let text = MessageEventContent::Text(as_reply(TextMessageEventContent::plain(
+            "> <@alice:example.org> Hi\n\nHello!",
+        )));
+        assert_eq!(text.accessibility_label(), "Hello!");
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn thread_reply_builder() {
+        let content = ThreadReplyBuilder::new(
+            event_id!("$root:example.org"),
+            event_id!("$latest:example.org"),
+            "Sounds good",
+        )
+        .reply_to(
+            event_id!("$target:example.org"),
+            &user_id!("@alice:example.org"),
+            "Lunch at noon?\nOr later?",
+        )
+        .build();
+
+        assert_eq!(
+            to_json_value(MessageEventContent::Text(content)).unwrap(),
+            json!({
+                "msgtype": "m.text",
+                "body": "> <@alice:example.org> Lunch at noon?\n> Or later?\n\nSounds good",
+                "m.relates_to": {
+                    "rel_type": "m.thread",
+                    "event_id": "$root:example.org",
+                    "m.in_reply_to": {
+                        "event_id": "$target:example.org",
+                    },
+                },
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn thread_message_builder() {
+        let content = ThreadReplyBuilder::new(
+            event_id!("$root:example.org"),
+            event_id!("$latest:example.org"),
+            "Sounds good",
+        )
+        .build();
+
+        assert_eq!(
+            to_json_value(MessageEventContent::Text(content)).unwrap(),
+            json!({
+                "msgtype": "m.text",
+                "body": "Sounds good",
+                "m.relates_to": {
+                    "rel_type": "m.thread",
+                    "event_id": "$root:example.org",
+                    "m.in_reply_to": {
+                        "event_id": "$latest:example.org",
+                    },
+                    "is_falling_back": true,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn quote_single_line() {
+        let (plain, formatted) = FormattedBody::quote("1 < 2");
+
+        assert_eq!(plain, "> 1 < 2");
+        assert_eq!(formatted.format, MessageFormat::Html);
+        assert_eq!(formatted.body, "<blockquote>1 &lt; 2</blockquote>");
+    }
+
+    #[test]
+    fn quote_multi_line() {
+        let (plain, formatted) = FormattedBody::quote("First line\nSecond line");
+
+        assert_eq!(plain, "> First line\n> Second line");
+        assert_eq!(formatted.body, "<blockquote>First line<br>Second line</blockquote>");
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn as_edit() {
+        let edit = MessageEventContent::Text(
+            TextMessageEventContent::plain("Hello!")
+                .make_replacement(event_id!("$original:example.org")),
+        );
+        assert_eq!(edit.as_edit(), Some((&event_id!("$original:example.org"), "m.text")));
+
+        assert_eq!(MessageEventContent::text_plain("Hello!").as_edit(), None);
+    }
+
+    #[test]
+    fn colored() {
+        let formatted = FormattedBody::colored("Alert", Some("#ff0000"), Some("#00FF00")).unwrap();
+        assert_eq!(formatted.format, MessageFormat::Html);
+        assert_eq!(
+            formatted.body,
+            r##"<font data-mx-color="#ff0000" data-mx-bg-color="#00FF00">Alert</font>"##
+        );
+
+        assert!(FormattedBody::colored("Alert", Some("red\"><script>"), None).is_err());
+    }
+
+    #[test]
+    fn formatted_reply_serialize_deserialize() {
+        let content = MessageEventContent::Text(TextMessageEventContent {
+            relates_to: Some(Relation::Reply {
+                in_reply_to: InReplyTo::new(event_id!("$15827405538098VGFWH:example.com")),
+                custom: BTreeMap::new(),
+            }),
+            ..TextMessageEventContent::html(
+                "> <@test:example.com> test\n\ntest reply",
+                "<mx-reply><blockquote>test</blockquote></mx-reply><b>test reply</b>",
+            )
+        });
+        let json_data = json!({
+            "body": "> <@test:example.com> test\n\ntest reply",
+            "format": "org.matrix.custom.html",
+            "formatted_body": "<mx-reply><blockquote>test</blockquote></mx-reply><b>test reply</b>",
+            "msgtype": "m.text",
+            "m.relates_to": {
+                "m.in_reply_to": {
+                    "event_id": "$15827405538098VGFWH:example.com"
+                }
+            }
+        });
+
+        assert_eq!(to_json_value(&content).unwrap(), json_data);
+
+        assert_matches!(
+            from_json_value::<MessageEventContent>(json_data).unwrap(),
+            MessageEventContent::Text(TextMessageEventContent {
+                formatted: Some(FormattedBody { format: MessageFormat::Html, body: html_body }),
+                relates_to: Some(Relation::Reply { in_reply_to, .. }),
+                ..
+            }) if html_body == "<mx-reply><blockquote>test</blockquote></mx-reply><b>test reply</b>"
+                && in_reply_to.event_id == event_id!("$15827405538098VGFWH:example.com")
+        );
+    }
+
+    #[test]
+    fn best_effort_text() {
+        let valid = from_json_value::<Raw<MessageEventContent>>(json!({
+            "body": "> <@alice:example.org> Hi\n\nHello!",
+            "msgtype": "m.text",
+            "m.relates_to": { "m.in_reply_to": { "event_id": "$original:example.org" } },
+        }))
+        .unwrap();
+        assert_eq!(MessageEventContent::best_effort_text(&valid), "Hello!");
+
+        let without_msgtype =
+            from_json_value::<Raw<MessageEventContent>>(json!({ "body": "Hello!" })).unwrap();
+        assert_eq!(MessageEventContent::best_effort_text(&without_msgtype), "Hello!");
+
+        let reply_without_msgtype = from_json_value::<Raw<MessageEventContent>>(json!({
+            "body": "> <@alice:example.org> Hi\n\nHello!",
+            "m.relates_to": { "m.in_reply_to": { "event_id": "$original:example.org" } },
+        }))
+        .unwrap();
+        assert_eq!(MessageEventContent::best_effort_text(&reply_without_msgtype), "Hello!");
+
+        let quote_without_msgtype =
+            from_json_value::<Raw<MessageEventContent>>(json!({ "body": "> quote" })).unwrap();
+        assert_eq!(MessageEventContent::best_effort_text(&quote_without_msgtype), "> quote");
+
+        let without_body =
+            from_json_value::<Raw<MessageEventContent>>(json!({ "msgtype": "m.text" })).unwrap();
+        assert_eq!(MessageEventContent::best_effort_text(&without_body), "[Unsupported message]");
+    }
+
+    #[test]
+    fn map_body() {
+        let text =
+            MessageEventContent::text_html("Hello", "<b>Hello</b>").map_body(str::to_uppercase);
+        assert_matches!(
+            text,
+            MessageEventContent::Text(TextMessageEventContent {
+                body,
+                formatted: Some(formatted),
+                ..
+            }) if body == "HELLO" && formatted.body == "<b>Hello</b>"
+        );
+
+        let image = MessageEventContent::Image(ImageMessageEventContent {
+            body: "sunset.png".into(),
+            ..ImageMessageEventContent::with_default_body("mxc://example.org/image", None)
+        })
+        .map_body(str::to_uppercase);
+        assert_matches!(
+            image,
+            MessageEventContent::Image(ImageMessageEventContent { body, url: Some(url), .. })
+            if body == "SUNSET.PNG" && url == "mxc://example.org/image"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn image_blurhash() {
+        let json_data = json!({
+            "body": "Image",
+            "msgtype": "m.image",
+            "url": "mxc://example.org/image",
+            "info": {
+                "mimetype": "image/png",
+                "xyz.amorgan.blurhash": "LEHV6nWB2yk8pyo0adR*.7kCMdnj",
+            },
+        });
+
+        let content = from_json_value::<MessageEventContent>(json_data.clone()).unwrap();
+        assert_matches!(
+            &content,
+            MessageEventContent::Image(image)
+            if image.blurhash() == Some("LEHV6nWB2yk8pyo0adR*.7kCMdnj")
+        );
+        assert_eq!(to_json_value(&content).unwrap(), json_data);
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn image_without_blurhash() {
+        let content = from_json_value::<ImageMessageEventContent>(json!({
+            "body": "Image",
+            "url": "mxc://example.org/image",
+            "info": { "mimetype": "image/png" },
+        }))
+        .unwrap();
+        assert_eq!(content.blurhash(), None);
+        assert_eq!(
+            to_json_value(content.info.unwrap()).unwrap(),
+            json!({ "mimetype": "image/png" })
+        );
+
+        let video = VideoMessageEventContent::with_default_body("mxc://example.org/video", None);
+        assert_eq!(video.blurhash(), None);
+    }
+
+    #[test]
+    fn padded_msgtype_strict() {
+        let json_data = json!({
+            "body": "Hello",
+            "msgtype": " m.text ",
+        });
+
+        assert!(from_json_value::<MessageEventContent>(json_data).is_err());
+    }
+
+    #[test]
+    fn padded_msgtype_tolerant() {
+        let json_data = json!({
+            "body": "Hello",
+            "msgtype": " m.text\n",
+        });
+
+        assert_matches!(
+            MessageEventContent::from_json_tolerant(json_data).unwrap(),
+            MessageEventContent::Text(TextMessageEventContent { body, formatted: None, .. })
+            if body == "Hello"
+        );
+    }
+
+    #[test]
+    fn reply_fallback_depth_none() {
+        assert_eq!(TextMessageEventContent::plain("Hello").reply_fallback_depth(), 0);
+        assert_eq!(TextMessageEventContent::html("> Hi", "<b>Hi</b>").reply_fallback_depth(), 0);
+    }
+
+    #[test]
+    fn reply_fallback_depth_one() {
+        let plain = TextMessageEventContent::plain("> <@alice:example.org> Lunch?\n\nSure");
+        assert_eq!(plain.reply_fallback_depth(), 1);
+
+        let html = TextMessageEventContent::html(
+            "> <@alice:example.org> Lunch?\n\nSure",
+            "<mx-reply><blockquote>Lunch?</blockquote></mx-reply>Sure",
+        );
+        assert_eq!(html.reply_fallback_depth(), 1);
+    }
+
+    #[test]
+    fn reply_fallback_depth_two() {
+        let plain = TextMessageEventContent::plain(
+            "> <@bob:example.org> > <@alice:example.org> Lunch?\n> > \n> > Sure\n> \n> Where?\n\nHere",
+        );
+        assert_eq!(plain.reply_fallback_depth(), 2);
+
+        let html = TextMessageEventContent::html(
+            "> <@bob:example.org> Sure\n\nWhere?",
+            "<mx-reply><blockquote><mx-reply><blockquote>Lunch?</blockquote></mx-reply>Sure\
+             </blockquote></mx-reply>Where?",
+        );
+        assert_eq!(html.reply_fallback_depth(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn media_filename_serialization() {
+        let mut image =
+            ImageMessageEventContent::with_default_body("mxc://example.org/image", None);
+        image.body = "A sunset over the sea".into();
+        image.filename = Some("sunset.png".into());
+        assert_eq!(image.display_filename(), "sunset.png");
+
+        assert_eq!(
+            to_json_value(MessageEventContent::Image(image)).unwrap(),
+            json!({
+                "body": "A sunset over the sea",
+                "filename": "sunset.png",
+                "msgtype": "m.image",
+                "url": "mxc://example.org/image",
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn media_filename_absent_by_default() {
+        let audio = AudioMessageEventContent::with_default_body("mxc://example.org/audio", None);
+        assert_eq!(audio.filename, None);
+        assert_eq!(audio.display_filename(), "Audio");
+
+        let video = VideoMessageEventContent::with_default_body("mxc://example.org/video", None);
+        assert_eq!(
+            to_json_value(MessageEventContent::Video(video)).unwrap(),
+            json!({
+                "body": "Video",
+                "msgtype": "m.video",
+                "url": "mxc://example.org/video",
+            })
+        );
+    }
+
+    #[test]
+    fn file_display_filename() {
+        let file = FileMessageEventContent::with_default_body("mxc://example.org/file", None, None);
+        assert_eq!(file.display_filename(), "File");
+
+        let file = FileMessageEventContent {
+            body: "The report".into(),
+            ..FileMessageEventContent::with_default_body(
+                "mxc://example.org/file",
+                Some("report.pdf".into()),
+                None,
+            )
+        };
+        assert_eq!(file.display_filename(), "report.pdf");
+    }
+
+    #[test]
+    fn visit_collect_urls() {
+        struct UrlCollector(Vec<String>);
+
+        impl MessageVisitor for UrlCollector {
+            fn on_url(&mut self, url: &str) {
+                self.0.push(url.to_owned());
+            }
+        }
+
+        let content = MessageEventContent::Video(VideoMessageEventContent {
+            info: Some(Box::new(VideoInfo {
+                thumbnail_url: Some("mxc://example.org/thumbnail".into()),
+                ..video_info(1024, 1000)
+            })),
+            ..VideoMessageEventContent::with_default_body("mxc://example.org/video", None)
+        });
+
+        let mut collector = UrlCollector(Vec::new());
+        content.visit(&mut collector);
+        assert_eq!(collector.0, vec!["mxc://example.org/video", "mxc://example.org/thumbnail"]);
+
+        let mut collector = UrlCollector(Vec::new());
+        MessageEventContent::text_plain("mxc://example.org/not-a-url").visit(&mut collector);
+        assert!(collector.0.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn thread_root() {
+        let content = from_json_value::<MessageEventContent>(json!({
+            "body": "In the thread",
+            "msgtype": "m.text",
+            "m.relates_to": {
+                "rel_type": "m.thread",
+                "event_id": "$root",
+                "m.in_reply_to": { "event_id": "$latest" },
+                "is_falling_back": true,
+            },
+        }))
+        .unwrap();
+        assert_eq!(content.thread_root(), Some(&event_id!("$root")));
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn thread_root_not_in_thread() {
+        let reply = MessageEventContent::text_plain("> <@alice:example.org> Hi\n\nHello")
+            .with_relation(Some(Relation::Reply {
+                in_reply_to: InReplyTo::new(event_id!("$latest")),
+                custom: BTreeMap::new(),
+            }));
+        assert_eq!(reply.thread_root(), None);
+        assert_eq!(MessageEventContent::text_plain("Hello").thread_root(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn notice_make_replacement() {
+        let content = NoticeMessageEventContent::html("Status: **up**", "Status: <b>up</b>")
+            .make_replacement(event_id!("$status:example.org"));
+
+        assert_eq!(
+            to_json_value(MessageEventContent::Notice(content)).unwrap(),
+            json!({
+                "body": "* Status: **up**",
+                "format": "org.matrix.custom.html",
+                "formatted_body": "* Status: <b>up</b>",
+                "msgtype": "m.notice",
+                "m.new_content": {
+                    "body": "Status: **up**",
+                    "format": "org.matrix.custom.html",
+                    "formatted_body": "Status: <b>up</b>",
+                    "msgtype": "m.notice",
+                },
+                "m.relates_to": {
+                    "rel_type": "m.replace",
+                    "event_id": "$status:example.org",
+                },
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn mentions_room() {
+        let content = from_json_value::<TextMessageEventContent>(json!({
+            "body": "@room Meeting in five minutes",
+            "m.mentions": { "room": true },
+        }))
+        .unwrap();
+
+        let mentions = content.mentions.unwrap();
+        assert!(mentions.mentions_room());
+        assert!(!mentions.mentions_user(&user_id!("@alice:example.org")));
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn mentions_user() {
+        let mentions =
+            Mentions { user_ids: vec![user_id!("@alice:example.org")], ..Mentions::new() };
+        assert!(!mentions.mentions_room());
+        assert!(mentions.mentions_user(&user_id!("@alice:example.org")));
+        assert!(!mentions.mentions_user(&user_id!("@bob:example.org")));
+
+        let content = TextMessageEventContent {
+            mentions: Some(mentions),
+            ..TextMessageEventContent::plain("Alice: Hi")
+        };
+        assert_eq!(
+            to_json_value(&content).unwrap(),
+            json!({
+                "body": "Alice: Hi",
+                "m.mentions": { "user_ids": ["@alice:example.org"] },
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn redact_sensitive() {
+        let patterns = [Regex::new(r#"access_token=[^&\s"]+"#).unwrap()];
+        let content = MessageEventContent::text_html(
+            "See https://example.org/media?access_token=s3cr3t&size=2 for details",
+            "See <a href=\"https://example.org/media?access_token=s3cr3t\">this</a> for details",
+        )
+        .redact_sensitive(&patterns);
+
+        assert_matches!(
+            content,
+            MessageEventContent::Text(TextMessageEventContent {
+                body,
+                formatted: Some(formatted),
+                ..
+            }) if body == "See https://example.org/media?[redacted]&size=2 for details"
+                && formatted.body
+                    == "See <a href=\"https://example.org/media?[redacted]\">this</a> for details"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn redact_sensitive_no_match() {
+        let patterns = [Regex::new(r"access_token=[^&\s]+").unwrap()];
+        let content =
+            MessageEventContent::notice_plain("Nothing to hide here").redact_sensitive(&patterns);
+        assert_eq!(content.body(), "Nothing to hide here");
+    }
+
+    #[test]
+    fn try_from_matching_variant() {
+        let content = MessageEventContent::text_plain("Hello");
+        let text = TextMessageEventContent::try_from(content).unwrap();
+        assert_eq!(text.body, "Hello");
+    }
+
+    #[test]
+    fn try_from_wrong_variant() {
+        let content = MessageEventContent::notice_plain("Hello");
+        let err = TextMessageEventContent::try_from(content).unwrap_err();
+        assert_eq!(err.expected(), "m.text");
+        assert_eq!(err.to_string(), "expected a message of type `m.text`, found `m.notice`");
+        assert_matches!(
+            err.into_content(),
+            MessageEventContent::Notice(NoticeMessageEventContent { body, .. }) if body == "Hello"
+        );
+    }
+
+    fn original_message() -> MessageEvent<MessageEventContent> {
+        MessageEvent {
+            content: MessageEventContent::text_plain("Lunch?\nAt noon?"),
+            event_id: event_id!("$original:example.org"),
+            origin_server_ts: UNIX_EPOCH + Duration::from_millis(10_000),
+            room_id: room_id!("!room:example.org"),
+            sender: user_id!("@alice:example.org"),
+            unsigned: Unsigned::default(),
+        }
+    }
+
+    #[test]
+    fn reply_with_fallback() {
+        let reply =
+            TextMessageEventContent::reply_to_with_fallback(&original_message(), "Sure", true);
+
+        assert_eq!(reply.body, "> <@alice:example.org> Lunch?\n> At noon?\n\nSure");
+        assert_matches!(
+            reply.relates_to,
+            Some(Relation::Reply { in_reply_to, .. })
+            if in_reply_to.event_id == event_id!("$original:example.org")
+        );
+    }
+
+    #[test]
+    fn reply_without_fallback() {
+        let reply =
+            TextMessageEventContent::reply_to_with_fallback(&original_message(), "Sure", false);
+
+        assert_eq!(reply.body, "Sure");
+        assert_eq!(reply.reply_fallback_depth(), 0);
+        assert_matches!(
+            reply.relates_to,
+            Some(Relation::Reply { in_reply_to, .. })
+            if in_reply_to.event_id == event_id!("$original:example.org")
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn valid_lang() {
+        let mut content = TextMessageEventContent::plain("Hello");
+        content.set_lang(Some("en-US".into())).unwrap();
+
+        assert_eq!(
+            to_json_value(&content).unwrap(),
+            json!({
+                "body": "Hello",
+                "io.ruma.lang": "en-US",
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn invalid_lang() {
+        let mut content = NoticeMessageEventContent::plain("Hello");
+        content.set_lang(Some("de".into())).unwrap();
+
+        let err = content.set_lang(Some("en_US".into())).unwrap_err();
+        assert_eq!(err.to_string(), "invalid language tag `en_US`");
+        assert!(content.set_lang(Some("en--US".into())).is_err());
+        assert!(content.set_lang(Some("".into())).is_err());
+        assert_eq!(content.lang.as_deref(), Some("de"));
+    }
+
+    #[test]
+    fn custom_server_notice() {
+        let content = ServerNoticeMessageEventContent::custom(
+            "The server will restart at midnight",
+            "org.example.server_notice.maintenance",
+        );
+        assert_eq!(content.server_notice_type.as_ref(), "org.example.server_notice.maintenance");
+
+        assert_eq!(
+            to_json_value(MessageEventContent::ServerNotice(content)).unwrap(),
+            json!({
+                "body": "The server will restart at midnight",
+                "msgtype": "m.server_notice",
+                "server_notice_type": "org.example.server_notice.maintenance",
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "html")]
+    fn ensure_plain_body() {
+        let mut content =
+            TextMessageEventContent::html("", "<p>Release <b>1.0</b> is out!</p><p>Enjoy</p>");
+        content.ensure_plain_body();
+        assert_eq!(content.body, "Release 1.0 is out!\nEnjoy");
+
+        let mut content = TextMessageEventContent::html("Release 1.0", "<b>Release 1.0</b>");
+        content.ensure_plain_body();
+        assert_eq!(content.body, "Release 1.0");
+    }
+
+    #[test]
+    fn estimated_line_count_short() {
+        let content = MessageEventContent::text_plain("Hello!");
+        assert_eq!(content.estimated_line_count(80), 1);
+        assert_eq!(MessageEventContent::text_plain("").estimated_line_count(80), 1);
+    }
+
+    #[test]
+    fn estimated_line_count_wrapping() {
+        let content = MessageEventContent::Text(as_reply(TextMessageEventContent::plain(format!(
+            "> <@alice:example.org> Hi\n\n{}\n\nBye",
+            "a".repeat(50)
+        ))));
+        // 50 characters wrap to 3 lines at a width of 20, plus an empty line and the last line.
+        assert_eq!(content.estimated_line_count(20), 5);
+    }
+
+    #[test]
+    fn estimated_line_count_max_width() {
+        let content = MessageEventContent::text_plain("Hello,\nWorld!");
+        assert_eq!(content.estimated_line_count(usize::MAX), 2);
+    }
+
+    #[test]
+    fn file_info_with_encrypted_thumbnail() {
+        let info = FileInfo {
+            mimetype: Some("application/pdf".into()),
+            size: Some(uint!(4096)),
+            thumbnail_info: None,
+            thumbnail_url: Some("mxc://example.org/thumbnail".into()),
+            thumbnail_file: None,
+        }
+        .with_encrypted_thumbnail(
+            encrypted_file(),
+            ThumbnailInfo {
+                height: Some(uint!(64)),
+                width: Some(uint!(48)),
+                mimetype: Some("image/png".into()),
+                size: Some(uint!(512)),
+            },
+        );
+
+        assert_eq!(info.thumbnail_url, None);
+        assert_eq!(info.thumbnail_file.unwrap().url, "mxc://example.org/encrypted");
+        assert_eq!(info.thumbnail_info.unwrap().mimetype.as_deref(), Some("image/png"));
+    }
+
+    #[test]
+    fn representations_plain() {
+        let content = TextMessageEventContent::plain("Hello");
+        assert_eq!(content.representations(), vec![("text/plain", "Hello")]);
+    }
+
+    #[test]
+    fn representations_plain_and_html() {
+        let content = TextMessageEventContent::html("Hello", "<b>Hello</b>");
+        assert_eq!(
+            content.representations(),
+            vec![("text/plain", "Hello"), ("text/html", "<b>Hello</b>")]
+        );
+    }
+
+    #[test]
+    fn invisible_chars() {
+        let mut content =
+            TextMessageEventContent::html("fr\u{200b}ee mo\u{202e}ney", "<b>free</b>");
+        assert!(content.contains_invisible_chars());
+
+        content.strip_invisible_chars();
+        assert_eq!(content.body, "free money");
+        assert!(!content.contains_invisible_chars());
+    }
+
+    #[test]
+    fn no_invisible_chars() {
+        let content = TextMessageEventContent::plain("Hello, family 👨\u{200d}👩\u{200d}👧!");
+        assert!(!content.contains_invisible_chars());
+
+        let mut stray_joiner = TextMessageEventContent::plain("a\u{200d}b");
+        assert!(stray_joiner.contains_invisible_chars());
+        stray_joiner.strip_invisible_chars();
+        assert_eq!(stray_joiner.body, "ab");
+    }
+
+    #[test]
+    fn server_notice_with_relation() {
+        let content = MessageEventContent::ServerNotice(ServerNoticeMessageEventContent::custom(
+            "Usage is back to normal",
+            "org.example.server_notice.usage",
+        ))
+        .with_relation(Some(Relation::Reply {
+            in_reply_to: InReplyTo::new(event_id!("$warning:example.org")),
+            custom: BTreeMap::new(),
+        }));
+
+        let json_data = json!({
+            "body": "Usage is back to normal",
+            "msgtype": "m.server_notice",
+            "server_notice_type": "org.example.server_notice.usage",
+            "m.relates_to": {
+                "m.in_reply_to": { "event_id": "$warning:example.org" },
+            },
+        });
+        assert_eq!(to_json_value(&content).unwrap(), json_data);
+
+        assert_matches!(
+            from_json_value::<MessageEventContent>(json_data).unwrap().relates_to(),
+            Some(Relation::Reply { in_reply_to, .. })
+            if in_reply_to.event_id == event_id!("$warning:example.org")
+        );
+    }
+
+    #[test]
+    fn server_notice_without_relation() {
+        let content = from_json_value::<MessageEventContent>(json!({
+            "body": "Usage limit reached",
+            "msgtype": "m.server_notice",
+            "server_notice_type": "m.server_notice.usage_limit_reached",
+            "limit_type": "monthly_active_user",
+        }))
+        .unwrap();
+        assert!(content.relates_to().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn sticker_like_image() {
+        let content = MessageEventContent::Image(ImageMessageEventContent::sticker_like(
+            "Cat waving",
+            "mxc://example.org/cat",
+            None,
+        ));
+        let json_data = json!({
+            "body": "Cat waving",
+            "msgtype": "m.image",
+            "url": "mxc://example.org/cat",
+            "io.ruma.sticker_like": true,
+        });
+        assert_eq!(to_json_value(&content).unwrap(), json_data);
+
+        assert_matches!(
+            from_json_value::<MessageEventContent>(json_data).unwrap(),
+            MessageEventContent::Image(ImageMessageEventContent { sticker_like: true, .. })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn sticker_like_defaults_to_false() {
+        let content = from_json_value::<ImageMessageEventContent>(json!({
+            "body": "Cat",
+            "url": "mxc://example.org/cat",
+        }))
+        .unwrap();
+        assert!(!content.sticker_like);
+
+        let image = ImageMessageEventContent::with_default_body("mxc://example.org/cat", None);
+        assert!(to_json_value(image).unwrap().get("io.ruma.sticker_like").is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "html")]
+    fn headings() {
+        let formatted = FormattedBody::html(
+            "<h1>Release notes</h1><p>Intro</p><h2>Fixes &amp; <em>improvements</em></h2>\
+             <p>Details</p><h3>Internal</h3>",
+        );
+        assert_eq!(
+            formatted.headings(),
+            vec![
+                (1, "Release notes".to_owned()),
+                (2, "Fixes & improvements".to_owned()),
+                (3, "Internal".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "html")]
+    fn no_headings() {
+        let formatted = FormattedBody::html("<p>Just a <b>paragraph</b></p>");
+        assert!(formatted.headings().is_empty());
     }
 
-    /// A convenience constructor to create an html message.
-    pub fn html(body: impl Into<String>, html_body: impl Into<String>) -> Self {
-        Self {
-            body: body.into(),
-            formatted: Some(FormattedBody::html(html_body)),
-            relates_to: None,
-        }
+    #[test]
+    #[cfg(feature = "html")]
+    fn collapsible_sections() {
+        let formatted = FormattedBody::html(
+            "<p>Build failed</p><details><summary>Full <b>log</b></summary>\
+             <pre><code>error[E0308]: mismatched types</code></pre></details>",
+        );
+
+        assert_eq!(
+            formatted.collapsible_sections(),
+            vec![(
+                "Full log".to_owned(),
+                "<pre><code>error[E0308]: mismatched types</code></pre>".to_owned()
+            )]
+        );
     }
 
-    /// A convenience constructor to create a plain text message.
-    #[deprecated = "Renamed to plain"]
-    pub fn new_plain(body: impl Into<String>) -> Self {
-        Self::plain(body)
+    #[test]
+    #[cfg(feature = "html")]
+    fn absolutize_room_alias_link() {
+        let mut formatted =
+            FormattedBody::html(r##"Join <a href="#room:example.org">#room:example.org</a>!"##);
+        formatted.absolutize_matrix_links("https://matrix.to");
+
+        assert_eq!(
+            formatted.body,
+            r##"Join <a href="https://matrix.to/#/%23room:example.org">#room:example.org</a>!"##
+        );
     }
-}
 
-/// The payload for a video message.
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct VideoMessageEventContent {
-    /// A description of the video, e.g. "Gangnam Style," or some kind of content description for
-    /// accessibility, e.g. "video attachment."
-    pub body: String,
+    #[test]
+    fn clone_with_relation() {
+        let original = MessageEventContent::text_html("Hello", "<b>Hello</b>");
+        let reply = original.clone_with_relation(Some(Relation::Reply {
+            in_reply_to: InReplyTo::new(event_id!("$original:example.org")),
+            custom: BTreeMap::new(),
+        }));
 
-    /// Metadata about the video clip referred to in `url`.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub info: Option<Box<VideoInfo>>,
+        assert!(original.relates_to().is_none());
+        assert_matches!(
+            &reply,
+            MessageEventContent::Text(TextMessageEventContent {
+                body,
+                formatted: Some(formatted),
+                relates_to: Some(Relation::Reply { in_reply_to, .. }),
+                ..
+            }) if body == "Hello"
+                && formatted.body == "<b>Hello</b>"
+                && in_reply_to.event_id == event_id!("$original:example.org")
+        );
 
-    /// The URL to the video clip.  Required if the file is unencrypted. The URL (typically
-    /// [MXC URI](https://matrix.org/docs/spec/client_server/r0.6.1#mxc-uri)) to the video clip.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub url: Option<String>,
+        assert!(reply.clone_with_relation(None).relates_to().is_none());
+    }
 
-    /// Required if video clip is encrypted. Information on the encrypted video clip.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub file: Option<Box<EncryptedFile>>,
-}
+    #[test]
+    fn media_info_category() {
+        let thumbnail_info = ThumbnailInfo {
+            height: None,
+            width: None,
+            mimetype: Some("image/png".into()),
+            size: None,
+        };
+        assert_eq!(thumbnail_info.category(), MediaCategory::Image);
 
-/// Metadata about a video.
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct VideoInfo {
-    /// The duration of the video in milliseconds.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub duration: Option<UInt>,
+        let mut file_info = FileInfo {
+            mimetype: Some("application/pdf".into()),
+            size: None,
+            thumbnail_info: None,
+            thumbnail_url: None,
+            thumbnail_file: None,
+        };
+        assert_eq!(file_info.category(), MediaCategory::Application);
 
-    /// The height of the video in pixels.
-    #[serde(rename = "h")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub height: Option<UInt>,
+        file_info.mimetype = None;
+        assert_eq!(file_info.category(), MediaCategory::Other);
 
-    /// The width of the video in pixels.
-    #[serde(rename = "w")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub width: Option<UInt>,
+        assert_eq!(video_info(1_000, 1_000).category(), MediaCategory::Video);
+    }
 
-    /// The mimetype of the video, e.g. "video/mp4."
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub mimetype: Option<String>,
+    #[test]
+    fn media_info_human_size() {
+        let mut info = FileInfo {
+            mimetype: None,
+            size: None,
+            thumbnail_info: None,
+            thumbnail_url: None,
+            thumbnail_file: None,
+        };
+        assert_eq!(info.human_size(), None);
 
-    /// The size of the video in bytes.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub size: Option<UInt>,
+        info.size = Some(uint!(512));
+        assert_eq!(info.human_size().as_deref(), Some("512 B"));
 
-    /// Metadata about an image.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub thumbnail_info: Option<Box<ThumbnailInfo>>,
+        info.size = Some(uint!(2_500));
+        assert_eq!(info.human_size().as_deref(), Some("2.5 kB"));
 
-    /// The URL (typically [MXC URI](https://matrix.org/docs/spec/client_server/r0.6.1#mxc-uri)) to
-    /// an image thumbnail of the video clip. Only present if the thumbnail is unencrypted.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub thumbnail_url: Option<String>,
+        info.size = Some(uint!(999_999));
+        assert_eq!(info.human_size().as_deref(), Some("1.0 MB"));
 
-    /// Information on the encrypted thumbnail file.  Only present if the thumbnail is encrypted.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub thumbnail_file: Option<Box<EncryptedFile>>,
-}
+        assert_eq!(video_info(1_234_567, 1_000).human_size().as_deref(), Some("1.2 MB"));
+    }
 
-#[cfg(test)]
-mod tests {
-    use std::time::{Duration, UNIX_EPOCH};
+    #[test]
+    fn displayed_content_of_reply() {
+        let content = MessageEventContent::Text(as_reply(TextMessageEventContent::html(
+            "> <@alice:example.org> Lunch?\n\nSure",
+            "<mx-reply><blockquote><mx-reply>Nested</mx-reply>Lunch?</blockquote></mx-reply>Sure",
+        )));
 
-    use matches::assert_matches;
-    use ruma_common::Raw;
-    use ruma_identifiers::{event_id, room_id, user_id};
-    use serde_json::{from_value as from_json_value, json, to_value as to_json_value};
+        assert_matches!(
+            content.displayed_content(),
+            MessageEventContent::Text(TextMessageEventContent {
+                body,
+                formatted: Some(formatted),
+                ..
+            }) if body == "Sure" && formatted.body == "Sure"
+        );
 
-    use super::{
-        AudioMessageEventContent, FormattedBody, MessageEventContent, MessageFormat, Relation,
-    };
-    use crate::{
-        room::{message::TextMessageEventContent, relationships::InReplyTo},
-        MessageEvent, Unsigned,
-    };
+        let plain = MessageEventContent::notice_plain("Hello");
+        assert_matches!(
+            plain.displayed_content(),
+            MessageEventContent::Notice(NoticeMessageEventContent { body, formatted: None, .. })
+                if body == "Hello"
+        );
+    }
 
     #[test]
-    fn serialization() {
-        let ev = MessageEvent {
-            content: MessageEventContent::Audio(AudioMessageEventContent {
-                body: "test".into(),
-                info: None,
-                url: Some("http://example.com/audio.mp3".into()),
-                file: None,
-            }),
-            event_id: event_id!("$143273582443PhrSn:example.org"),
-            origin_server_ts: UNIX_EPOCH + Duration::from_millis(10_000),
-            room_id: room_id!("!testroomid:example.org"),
-            sender: user_id!("@user:example.org"),
-            unsigned: Unsigned::default(),
-        };
+    #[cfg(feature = "unstable-pre-spec")]
+    fn displayed_content_of_edit() {
+        let edit = MessageEventContent::Text(
+            TextMessageEventContent::html("Fixed", "<b>Fixed</b>")
+                .make_replacement(event_id!("$original:example.org")),
+        );
+        assert_eq!(edit.body(), "* Fixed");
 
-        assert_eq!(
-            to_json_value(ev).unwrap(),
-            json!({
-                "type": "m.room.message",
-                "event_id": "$143273582443PhrSn:example.org",
-                "origin_server_ts": 10_000,
-                "room_id": "!testroomid:example.org",
-                "sender": "@user:example.org",
-                "content": {
-                    "body": "test",
-                    "msgtype": "m.audio",
-                    "url": "http://example.com/audio.mp3",
-                }
-            })
+        assert_matches!(
+            edit.displayed_content(),
+            MessageEventContent::Text(TextMessageEventContent {
+                body,
+                formatted: Some(formatted),
+                relates_to: None,
+                new_content: None,
+                ..
+            }) if body == "Fixed" && formatted.body == "<b>Fixed</b>"
         );
     }
 
     #[test]
-    fn content_serialization() {
-        let message_event_content = MessageEventContent::Audio(AudioMessageEventContent {
-            body: "test".into(),
-            info: None,
-            url: Some("http://example.com/audio.mp3".into()),
-            file: None,
-        });
+    fn raw_has_relation() {
+        let reply = to_raw_value(&json!({
+            "body": "Sure",
+            "msgtype": "m.text",
+            "m.relates_to": { "m.in_reply_to": { "event_id": "$original:example.org" } },
+        }))
+        .unwrap();
+        assert!(MessageEventContent::raw_has_relation(&reply));
 
-        assert_eq!(
-            to_json_value(&message_event_content).unwrap(),
-            json!({
-                "body": "test",
-                "msgtype": "m.audio",
-                "url": "http://example.com/audio.mp3"
-            })
+        let plain = to_raw_value(&json!({ "body": "Hello", "msgtype": "m.text" })).unwrap();
+        assert!(!MessageEventContent::raw_has_relation(&plain));
+
+        let null = to_raw_value(&json!({ "body": "Hello", "m.relates_to": null })).unwrap();
+        assert!(!MessageEventContent::raw_has_relation(&null));
+    }
+
+    #[test]
+    fn file_with_caption_and_filename() {
+        let content = FileMessageEventContent::with_caption_and_filename(
+            "mxc://example.org/report",
+            None,
+            "report.pdf",
+            "The quarterly report",
         );
+
+        assert_eq!(content.body, "The quarterly report");
+        assert_eq!(content.filename.as_deref(), Some("report.pdf"));
+        assert_eq!(content.display_filename(), "report.pdf");
     }
 
     #[test]
-    fn formatted_body_serialization() {
-        let message_event_content = MessageEventContent::Text(TextMessageEventContent {
-            body: "Hello, World!".into(),
-            formatted: Some(FormattedBody {
-                format: MessageFormat::Html,
-                body: "Hello, <em>World</em>!".into(),
-            }),
-            relates_to: None,
-        });
+    #[cfg(feature = "unstable-pre-spec")]
+    fn image_with_caption_and_filename() {
+        let content = ImageMessageEventContent::with_caption_and_filename(
+            "mxc://example.org/beach",
+            None,
+            "IMG_1234.jpg",
+            "Sunset at the beach",
+        );
 
         assert_eq!(
-            to_json_value(&message_event_content).unwrap(),
+            to_json_value(&content).unwrap(),
             json!({
-                "body": "Hello, World!",
-                "msgtype": "m.text",
-                "format": "org.matrix.custom.html",
-                "formatted_body": "Hello, <em>World</em>!",
+                "body": "Sunset at the beach",
+                "filename": "IMG_1234.jpg",
+                "url": "mxc://example.org/beach",
             })
         );
+        assert_eq!(content.display_filename(), "IMG_1234.jpg");
     }
 
     #[test]
-    fn plain_text_content_serialization() {
-        let message_event_content = MessageEventContent::Text(TextMessageEventContent::plain(
-            "> <@test:example.com> test\n\ntest reply",
-        ));
+    fn text_or_notice() {
+        assert_matches!(
+            MessageEventContent::text_or_notice(true, "Build passed", None),
+            MessageEventContent::Notice(NoticeMessageEventContent {
+                body,
+                formatted: None,
+                ..
+            }) if body == "Build passed"
+        );
 
-        assert_eq!(
-            to_json_value(&message_event_content).unwrap(),
-            json!({
-                "body": "> <@test:example.com> test\n\ntest reply",
-                "msgtype": "m.text"
-            })
+        assert_matches!(
+            MessageEventContent::text_or_notice(
+                false,
+                "Build passed",
+                Some(FormattedBody::html("<b>Build passed</b>"))
+            ),
+            MessageEventContent::Text(TextMessageEventContent {
+                body,
+                formatted: Some(formatted),
+                ..
+            }) if body == "Build passed" && formatted.body == "<b>Build passed</b>"
         );
     }
 
     #[test]
-    fn relates_to_content_serialization() {
-        let message_event_content = MessageEventContent::Text(TextMessageEventContent {
-            body: "> <@test:example.com> test\n\ntest reply".to_owned(),
-            formatted: None,
-            relates_to: Some(Relation::Reply {
-                in_reply_to: InReplyTo { event_id: event_id!("$15827405538098VGFWH:example.com") },
-            }),
-        });
+    fn to_json_pretty() {
+        let pretty = MessageEventContent::notice_plain("Hello").to_json_pretty();
 
-        let json_data = json!({
-            "body": "> <@test:example.com> test\n\ntest reply",
-            "msgtype": "m.text",
-            "m.relates_to": {
-                "m.in_reply_to": {
-                    "event_id": "$15827405538098VGFWH:example.com"
-                }
-            }
-        });
-
-        assert_eq!(to_json_value(&message_event_content).unwrap(), json_data);
+        assert!(pretty.lines().any(|line| line.trim() == r#""msgtype": "m.notice","#
+            || line.trim() == r#""msgtype": "m.notice""#));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&pretty).unwrap(),
+            json!({ "body": "Hello", "msgtype": "m.notice" })
+        );
     }
 
     #[test]
-    #[cfg(not(feature = "unstable-pre-spec"))]
-    fn edit_deserialization() {
-        let json_data = json!({
-            "body": "test",
+    #[cfg(feature = "unstable-pre-spec")]
+    fn url_previews_serialization() {
+        let content = TextMessageEventContent::plain("Look at https://example.org")
+            .with_url_previews(vec![UrlPreview {
+                title: Some("Example Domain".into()),
+                description: Some("For use in examples".into()),
+                image: Some("mxc://example.org/preview".into()),
+                ..UrlPreview::new("https://example.org")
+            }]);
+        let json = json!({
+            "body": "Look at https://example.org",
             "msgtype": "m.text",
-            "m.relates_to": {
-                "rel_type": "m.replace",
-                "event_id": event_id!("$1598361704261elfgc:localhost"),
-            }
+            "m.url_previews": [{
+                "matched_url": "https://example.org",
+                "og:title": "Example Domain",
+                "og:description": "For use in examples",
+                "og:image": "mxc://example.org/preview",
+            }],
         });
 
+        assert_eq!(to_json_value(MessageEventContent::Text(content)).unwrap(), json);
         assert_matches!(
-            from_json_value::<MessageEventContent>(json_data).unwrap(),
+            from_json_value::<MessageEventContent>(json).unwrap(),
             MessageEventContent::Text(TextMessageEventContent {
-                body,
-                formatted: None,
-                relates_to: Some(Relation::Custom(_)),
-            }) if body == "test"
+                url_previews: Some(previews),
+                ..
+            }) if previews.len() == 1
+                && previews[0].matched_url.as_deref() == Some("https://example.org")
+                && previews[0].title.as_deref() == Some("Example Domain")
+                && previews[0].image.as_deref() == Some("mxc://example.org/preview")
         );
     }
 
     #[test]
-    fn content_deserialization() {
-        let json_data = json!({
-            "body": "test",
-            "msgtype": "m.audio",
-            "url": "http://example.com/audio.mp3"
-        });
-
+    #[cfg(feature = "unstable-pre-spec")]
+    fn empty_url_previews_deserialization() {
         assert_matches!(
-            from_json_value::<Raw<MessageEventContent>>(json_data)
-                .unwrap()
-                .deserialize()
-                .unwrap(),
-            MessageEventContent::Audio(AudioMessageEventContent {
-                body,
-                info: None,
-                url: Some(url),
-                file: None,
-            }) if body == "test" && url == "http://example.com/audio.mp3"
+            from_json_value::<MessageEventContent>(json!({
+                "body": "https://example.org",
+                "msgtype": "m.text",
+                "m.url_previews": [],
+            }))
+            .unwrap(),
+            MessageEventContent::Text(TextMessageEventContent {
+                url_previews: Some(previews),
+                ..
+            }) if previews.is_empty()
         );
     }
 
     #[test]
-    fn content_deserialization_failure() {
-        let json_data = json!({
-            "body": "test","msgtype": "m.location",
-            "url": "http://example.com/audio.mp3"
-        });
-        assert!(from_json_value::<Raw<MessageEventContent>>(json_data)
-            .unwrap()
-            .deserialize()
-            .is_err());
+    fn reply_fallback_room() {
+        let reply = TextMessageEventContent::html(
+            "> <@alice:example.org> Lunch?\n\nSure",
+            "<mx-reply><blockquote>\
+             <a href=\"https://matrix.to/#/%21lunch%3Aexample.org/$original:example.org\">\
+             In reply to</a> <a href=\"https://matrix.to/#/@alice:example.org\">@alice:example.org</a>\
+             <br>Lunch?</blockquote></mx-reply>Sure",
+        );
+        assert_eq!(reply.reply_fallback_room(), Some(room_id!("!lunch:example.org")));
+
+        let not_a_reply = TextMessageEventContent::html(
+            "Join #lunch:example.org",
+            "Join <a href=\"https://matrix.to/#/!lunch:example.org\">the room</a>",
+        );
+        assert_eq!(not_a_reply.reply_fallback_room(), None);
+        assert_eq!(
+            TextMessageEventContent::plain("> <@alice:example.org> Hi\n\nHey")
+                .reply_fallback_room(),
+            None
+        );
+    }
+
+    #[test]
+    fn percent_decode_escapes() {
+        assert_eq!(percent_decode("%21room%3Aexample.org"), "!room:example.org");
+        assert_eq!(percent_decode("%+1%-1%2"), "%+1%-1%2");
+        assert_eq!(percent_decode("%zz%"), "%zz%");
     }
 }
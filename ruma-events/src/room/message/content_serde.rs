@@ -0,0 +1,265 @@
+//! Serialization of the message types with a formatted body.
+//!
+//! `formatted` is flattened into these message types when deserializing. A derived `Serialize`
+//! implementation for flattened fields doesn't know the number of fields upfront, which formats
+//! like MessagePack need, so the fields are serialized one by one instead.
+//!
+//! New fields of these types have to be added to both the field count and the serialization
+//! below. The tests compare the output with that of a derived implementation.
+
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+use super::{
+    EmoteMessageEventContent, FormattedBody, NoticeMessageEventContent, TextMessageEventContent,
+};
+
+impl Serialize for EmoteMessageEventContent {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let len = 1 + formatted_len(&self.formatted);
+
+        let mut st = serializer.serialize_struct("EmoteMessageEventContent", len)?;
+        st.serialize_field("body", &self.body)?;
+        serialize_formatted(&mut st, &self.formatted)?;
+        st.end()
+    }
+}
+
+impl Serialize for NoticeMessageEventContent {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let len = 1 + formatted_len(&self.formatted) + opt_len(&self.relates_to);
+        #[cfg(feature = "unstable-pre-spec")]
+        let len = len + opt_len(&self.new_content) + opt_len(&self.mentions) + opt_len(&self.lang);
+
+        let mut st = serializer.serialize_struct("NoticeMessageEventContent", len)?;
+        st.serialize_field("body", &self.body)?;
+        serialize_formatted(&mut st, &self.formatted)?;
+        serialize_opt(&mut st, "m.relates_to", &self.relates_to)?;
+        #[cfg(feature = "unstable-pre-spec")]
+        {
+            serialize_opt(&mut st, "m.new_content", &self.new_content)?;
+            serialize_opt(&mut st, "m.mentions", &self.mentions)?;
+            serialize_opt(&mut st, "io.ruma.lang", &self.lang)?;
+        }
+        st.end()
+    }
+}
+
+impl Serialize for TextMessageEventContent {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let len = 1 + formatted_len(&self.formatted) + opt_len(&self.relates_to);
+        #[cfg(feature = "unstable-pre-spec")]
+        let len = len
+            + opt_len(&self.new_content)
+            + opt_len(&self.mentions)
+            + opt_len(&self.lang)
+            + opt_len(&self.url_previews);
+
+        let mut st = serializer.serialize_struct("TextMessageEventContent", len)?;
+        st.serialize_field("body", &self.body)?;
+        serialize_formatted(&mut st, &self.formatted)?;
+        serialize_opt(&mut st, "m.relates_to", &self.relates_to)?;
+        #[cfg(feature = "unstable-pre-spec")]
+        {
+            serialize_opt(&mut st, "m.new_content", &self.new_content)?;
+            serialize_opt(&mut st, "m.mentions", &self.mentions)?;
+            serialize_opt(&mut st, "io.ruma.lang", &self.lang)?;
+            serialize_opt(&mut st, "m.url_previews", &self.url_previews)?;
+        }
+        st.end()
+    }
+}
+
+/// The number of fields `formatted` is serialized as.
+fn formatted_len(formatted: &Option<FormattedBody>) -> usize {
+    if formatted.is_some() {
+        2
+    } else {
+        0
+    }
+}
+
+/// Serializes `format` and `formatted_body`, if `formatted` is set.
+fn serialize_formatted<S>(st: &mut S, formatted: &Option<FormattedBody>) -> Result<(), S::Error>
+where
+    S: SerializeStruct,
+{
+    match formatted {
+        Some(formatted) => {
+            st.serialize_field("format", &formatted.format)?;
+            st.serialize_field("formatted_body", &formatted.body)
+        }
+        None => {
+            st.skip_field("format")?;
+            st.skip_field("formatted_body")
+        }
+    }
+}
+
+/// The number of fields an optional field is serialized as.
+fn opt_len<T>(value: &Option<T>) -> usize {
+    usize::from(value.is_some())
+}
+
+/// Serializes an optional field, skipping it if it is `None`.
+fn serialize_opt<S, T>(st: &mut S, key: &'static str, value: &Option<T>) -> Result<(), S::Error>
+where
+    S: SerializeStruct,
+    T: Serialize,
+{
+    match value {
+        Some(value) => st.serialize_field(key, value),
+        None => st.skip_field(key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use ruma_identifiers::event_id;
+    #[cfg(feature = "unstable-pre-spec")]
+    use ruma_identifiers::user_id;
+    use serde::{Deserialize, Serialize};
+    use serde_json::{to_value as to_json_value, value::Serializer as JsonSerializer};
+
+    #[cfg(feature = "unstable-pre-spec")]
+    use crate::room::message::{Mentions, MessageEventContent, UrlPreview};
+    use crate::room::{
+        message::{
+            EmoteMessageEventContent, FormattedBody, NoticeMessageEventContent, Relation,
+            TextMessageEventContent,
+        },
+        relationships::InReplyTo,
+    };
+
+    // These mirror the message types with a derived `Serialize` implementation. Since the derived
+    // `Deserialize` implementations of remote types construct them with a struct literal, they
+    // don't compile if a field is missing.
+
+    #[derive(Deserialize, Serialize)]
+    #[serde(remote = "EmoteMessageEventContent")]
+    struct DerivedEmote {
+        body: String,
+        #[serde(flatten)]
+        formatted: Option<FormattedBody>,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    #[serde(remote = "NoticeMessageEventContent")]
+    struct DerivedNotice {
+        body: String,
+        #[serde(flatten)]
+        formatted: Option<FormattedBody>,
+        #[serde(rename = "m.relates_to", skip_serializing_if = "Option::is_none")]
+        relates_to: Option<Relation>,
+        #[cfg(feature = "unstable-pre-spec")]
+        #[serde(rename = "m.new_content", skip_serializing_if = "Option::is_none")]
+        new_content: Option<Box<MessageEventContent>>,
+        #[cfg(feature = "unstable-pre-spec")]
+        #[serde(rename = "m.mentions", skip_serializing_if = "Option::is_none")]
+        mentions: Option<Mentions>,
+        #[cfg(feature = "unstable-pre-spec")]
+        #[serde(rename = "io.ruma.lang", skip_serializing_if = "Option::is_none")]
+        lang: Option<String>,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    #[serde(remote = "TextMessageEventContent")]
+    struct DerivedText {
+        body: String,
+        #[serde(flatten)]
+        formatted: Option<FormattedBody>,
+        #[serde(rename = "m.relates_to", skip_serializing_if = "Option::is_none")]
+        relates_to: Option<Relation>,
+        #[cfg(feature = "unstable-pre-spec")]
+        #[serde(rename = "m.new_content", skip_serializing_if = "Option::is_none")]
+        new_content: Option<Box<MessageEventContent>>,
+        #[cfg(feature = "unstable-pre-spec")]
+        #[serde(rename = "m.mentions", skip_serializing_if = "Option::is_none")]
+        mentions: Option<Mentions>,
+        #[cfg(feature = "unstable-pre-spec")]
+        #[serde(rename = "io.ruma.lang", skip_serializing_if = "Option::is_none")]
+        lang: Option<String>,
+        #[cfg(feature = "unstable-pre-spec")]
+        #[serde(rename = "m.url_previews", skip_serializing_if = "Option::is_none")]
+        url_previews: Option<Vec<UrlPreview>>,
+    }
+
+    fn reply() -> Option<Relation> {
+        Some(Relation::Reply {
+            in_reply_to: InReplyTo::new(event_id!("$original:example.org")),
+            custom: BTreeMap::new(),
+        })
+    }
+
+    #[cfg(feature = "unstable-pre-spec")]
+    fn mentions() -> Option<Mentions> {
+        Some(Mentions { user_ids: vec![user_id!("@alice:example.org")], room: true })
+    }
+
+    #[test]
+    fn emote_matches_derived() {
+        let content = EmoteMessageEventContent {
+            body: "waves".into(),
+            formatted: Some(FormattedBody::html("<em>waves</em>")),
+        };
+
+        assert_eq!(
+            to_json_value(&content).unwrap(),
+            DerivedEmote::serialize(&content, JsonSerializer).unwrap()
+        );
+    }
+
+    #[test]
+    fn notice_matches_derived() {
+        let content = NoticeMessageEventContent {
+            body: "Maintenance is over".into(),
+            formatted: Some(FormattedBody::html("Maintenance is <b>over</b>")),
+            relates_to: reply(),
+            #[cfg(feature = "unstable-pre-spec")]
+            new_content: Some(Box::new(MessageEventContent::notice_plain("Maintenance is over"))),
+            #[cfg(feature = "unstable-pre-spec")]
+            mentions: mentions(),
+            #[cfg(feature = "unstable-pre-spec")]
+            lang: Some("en".into()),
+        };
+
+        assert_eq!(
+            to_json_value(&content).unwrap(),
+            DerivedNotice::serialize(&content, JsonSerializer).unwrap()
+        );
+    }
+
+    #[test]
+    fn text_matches_derived() {
+        let content = TextMessageEventContent {
+            body: "See https://example.org".into(),
+            formatted: Some(FormattedBody::html(
+                r#"See <a href="https://example.org">example.org</a>"#,
+            )),
+            relates_to: reply(),
+            #[cfg(feature = "unstable-pre-spec")]
+            new_content: Some(Box::new(MessageEventContent::text_plain("See https://example.org"))),
+            #[cfg(feature = "unstable-pre-spec")]
+            mentions: mentions(),
+            #[cfg(feature = "unstable-pre-spec")]
+            lang: Some("en".into()),
+            #[cfg(feature = "unstable-pre-spec")]
+            url_previews: Some(vec![UrlPreview::new("https://example.org")]),
+        };
+
+        assert_eq!(
+            to_json_value(&content).unwrap(),
+            DerivedText::serialize(&content, JsonSerializer).unwrap()
+        );
+    }
+}
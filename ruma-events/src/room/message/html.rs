@@ -0,0 +1,851 @@
+//! A minimal tokenizer for the HTML subset used in formatted message bodies.
+//!
+//! This does not build a DOM, it only splits the input into tags, text and comments. It is lenient
+//! with malformed input: anything that can't be parsed as markup is treated as text.
+
+use std::{char, collections::BTreeMap, fmt};
+
+/// Elements that never have any content and no end tag.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Whether the element with the given lowercase name is a void element.
+pub(super) fn is_void_element(name: &str) -> bool {
+    VOID_ELEMENTS.contains(&name)
+}
+
+/// A token of an HTML document.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Token<'a> {
+    /// A start tag, e.g. `<a href="…">`.
+    StartTag {
+        /// The lowercase name of the element.
+        name: String,
+
+        /// The attributes of the element, with lowercase names and decoded values.
+        attrs: Vec<(String, String)>,
+
+        /// Whether the tag was closed with `/>`.
+        self_closing: bool,
+    },
+
+    /// An end tag, e.g. `</a>`.
+    EndTag {
+        /// The lowercase name of the element.
+        name: String,
+    },
+
+    /// Text, with character references not decoded yet.
+    Text(&'a str),
+
+    /// A comment, doctype or processing instruction, including its delimiters.
+    Comment(&'a str),
+}
+
+impl Token<'_> {
+    /// Whether this token opens an element that needs a matching end tag.
+    pub(super) fn opens_element(&self) -> bool {
+        match self {
+            Token::StartTag { name, self_closing, .. } => !self_closing && !is_void_element(name),
+            _ => false,
+        }
+    }
+
+    /// The value of the attribute with the given lowercase name, if this is a start tag.
+    pub(super) fn attr(&self, attr_name: &str) -> Option<&str> {
+        match self {
+            Token::StartTag { attrs, .. } => {
+                attrs.iter().find(|(name, _)| name == attr_name).map(|(_, value)| value.as_str())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// An iterator over the tokens of an HTML document.
+#[derive(Clone, Debug)]
+pub(super) struct Tokenizer<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    /// Creates a new tokenizer for the given input.
+    pub(super) fn new(input: &'a str) -> Self {
+        Self { input, pos: 0 }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    /// Takes the input until the next `<`, or until the end.
+    fn text(&mut self, skip: usize) -> Token<'a> {
+        let rest = self.rest();
+        let end = rest[skip..].find('<').map_or(rest.len(), |i| i + skip);
+        self.pos += end;
+        Token::Text(&rest[..end])
+    }
+
+    fn comment(&mut self) -> Token<'a> {
+        let rest = self.rest();
+        let end = if rest.starts_with("<!--") {
+            rest[4..].find("-->").map_or(rest.len(), |i| i + 7)
+        } else {
+            rest.find('>').map_or(rest.len(), |i| i + 1)
+        };
+        self.pos += end;
+        Token::Comment(&rest[..end])
+    }
+
+    fn end_tag(&mut self) -> Option<Token<'a>> {
+        let rest = self.rest();
+        let end = rest.find('>')?;
+        let name = rest[2..end].trim().to_ascii_lowercase();
+        self.pos += end + 1;
+        Some(Token::EndTag { name })
+    }
+
+    fn start_tag(&mut self) -> Option<Token<'a>> {
+        let rest = self.rest();
+        let bytes = rest.as_bytes();
+
+        let mut i = 1;
+        while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'-') {
+            i += 1;
+        }
+        let name = rest[1..i].to_ascii_lowercase();
+
+        let mut attrs = Vec::new();
+        let self_closing = loop {
+            while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+
+            match bytes.get(i)? {
+                b'>' => break false,
+                b'/' if bytes.get(i + 1) == Some(&b'>') => {
+                    i += 1;
+                    break true;
+                }
+                b'/' => {
+                    i += 1;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let name_start = i;
+            while i < bytes.len()
+                && !matches!(bytes[i], b'=' | b'>' | b'/')
+                && !bytes[i].is_ascii_whitespace()
+            {
+                i += 1;
+            }
+            let attr_name = rest[name_start..i].to_ascii_lowercase();
+
+            while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+
+            let value = if bytes.get(i) == Some(&b'=') {
+                i += 1;
+                while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                    i += 1;
+                }
+
+                match *bytes.get(i)? {
+                    quote @ b'"' | quote @ b'\'' => {
+                        let value_start = i + 1;
+                        let value_len = rest[value_start..].find(quote as char)?;
+                        i = value_start + value_len + 1;
+                        decode_entities(&rest[value_start..value_start + value_len])
+                    }
+                    _ => {
+                        let value_start = i;
+                        while i < bytes.len() && bytes[i] != b'>' && !bytes[i].is_ascii_whitespace()
+                        {
+                            i += 1;
+                        }
+                        decode_entities(&rest[value_start..i])
+                    }
+                }
+            } else {
+                String::new()
+            };
+
+            attrs.push((attr_name, value));
+        };
+
+        self.pos += i + 1;
+        Some(Token::StartTag { name, attrs, self_closing })
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let rest = self.rest();
+        let bytes = rest.as_bytes();
+
+        if rest.is_empty() {
+            return None;
+        }
+
+        if bytes[0] != b'<' {
+            return Some(self.text(0));
+        }
+
+        let token = match bytes.get(1) {
+            Some(b'!') | Some(b'?') => Some(self.comment()),
+            Some(b'/') if bytes.get(2).map_or(false, u8::is_ascii_alphabetic) => self.end_tag(),
+            Some(c) if c.is_ascii_alphabetic() => self.start_tag(),
+            _ => None,
+        };
+
+        // Anything that isn't valid markup, like a lone `<` or an unterminated tag, is text.
+        Some(token.unwrap_or_else(|| self.text(1)))
+    }
+}
+
+/// Decodes the character references in the given text.
+///
+/// Only the named references for characters that have to be escaped in HTML are supported, all
+/// other named references are left as-is.
+pub(super) fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest.find(';').map(|end| (&rest[1..end], end));
+        let replacement = entity.and_then(|(entity, end)| {
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                _ if entity.starts_with("#x") || entity.starts_with("#X") => {
+                    u32::from_str_radix(&entity[2..], 16).ok().and_then(char::from_u32)
+                }
+                _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(char::from_u32),
+                _ => None,
+            };
+
+            c.map(|c| (c, end))
+        });
+
+        match replacement {
+            Some((c, end)) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Escapes the given text so it can be used as the text content of an element.
+pub(super) fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Escapes the given text so it can be used as a double-quoted attribute value.
+pub(super) fn escape_attr(value: &str) -> String {
+    escape_text(value).replace('"', "&quot;")
+}
+
+/// The maximum nesting depth of elements kept by the sanitizer, as recommended by the spec.
+pub(super) const MAX_DEPTH: usize = 100;
+
+/// The elements allowed by the specification, with their allowed attributes.
+const ALLOWED_ELEMENTS: &[(&str, &[&str])] = &[
+    ("font", &["data-mx-bg-color", "data-mx-color", "color"]),
+    ("del", &[]),
+    ("h1", &[]),
+    ("h2", &[]),
+    ("h3", &[]),
+    ("h4", &[]),
+    ("h5", &[]),
+    ("h6", &[]),
+    ("blockquote", &[]),
+    ("p", &[]),
+    ("a", &["name", "target", "href"]),
+    ("ul", &[]),
+    ("ol", &["start"]),
+    ("sup", &[]),
+    ("sub", &[]),
+    ("li", &[]),
+    ("b", &[]),
+    ("i", &[]),
+    ("u", &[]),
+    ("strong", &[]),
+    ("em", &[]),
+    ("strike", &[]),
+    ("code", &["class"]),
+    ("hr", &[]),
+    ("br", &[]),
+    ("div", &[]),
+    ("table", &[]),
+    ("thead", &[]),
+    ("tbody", &[]),
+    ("tr", &[]),
+    ("th", &[]),
+    ("td", &[]),
+    ("caption", &[]),
+    ("pre", &[]),
+    ("span", &["data-mx-bg-color", "data-mx-color"]),
+    ("img", &["width", "height", "alt", "title", "src"]),
+];
+
+/// Elements that are removed together with their content.
+const DROPPED_CONTENT_ELEMENTS: &[&str] = &["script", "style"];
+
+/// Elements that start a new line when converting HTML to plain text.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "blockquote",
+    "div",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "table",
+    "tr",
+    "ul",
+];
+
+/// The URL schemes allowed in links.
+const ALLOWED_LINK_SCHEMES: &[&str] = &["https", "http", "ftp", "mailto", "magnet"];
+
+/// Configuration of the HTML sanitizer.
+///
+/// The default configuration uses the allow-list of the specification. Additional elements and
+/// attributes can be allowed for clients that agree on using them, e.g. `<details>`.
+#[derive(Clone, Debug, Default)]
+pub struct SanitizerConfig {
+    /// Elements allowed in addition to the ones of the specification, with the attributes allowed
+    /// on them.
+    ///
+    /// If an element allowed by the specification is listed here, the given attributes are
+    /// allowed on it in addition to the ones of the specification.
+    pub extra_elements: BTreeMap<String, Vec<String>>,
+}
+
+impl SanitizerConfig {
+    /// Creates a new `SanitizerConfig` using the allow-list of the specification.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows the given element with the given attributes, in addition to the allow-list of the
+    /// specification.
+    ///
+    /// Element and attribute names are matched in lowercase.
+    pub fn allow_element(mut self, element: &str, attributes: &[&str]) -> Self {
+        self.extra_elements
+            .entry(element.to_ascii_lowercase())
+            .or_default()
+            .extend(attributes.iter().map(|attr| attr.to_ascii_lowercase()));
+        self
+    }
+
+    /// Allows collapsible sections, i.e. `<details>` elements with an `open` attribute and
+    /// `<summary>` elements, in addition to the allow-list of the specification.
+    pub fn allow_collapsible_sections(self) -> Self {
+        self.allow_element("details", &["open"]).allow_element("summary", &[])
+    }
+
+    fn is_allowed_element(&self, element: &str) -> bool {
+        spec_allowed_attributes(element).is_some() || self.extra_elements.contains_key(element)
+    }
+
+    fn is_allowed_attribute(&self, element: &str, attr: &str) -> bool {
+        spec_allowed_attributes(element).map_or(false, |attrs| attrs.contains(&attr))
+            || self
+                .extra_elements
+                .get(element)
+                .map_or(false, |attrs| attrs.iter().any(|a| a == attr))
+    }
+}
+
+fn spec_allowed_attributes(element: &str) -> Option<&'static [&'static str]> {
+    ALLOWED_ELEMENTS.iter().find(|(name, _)| *name == element).map(|(_, attrs)| *attrs)
+}
+
+fn is_allowed_attr_value(element: &str, attr: &str, value: &str) -> bool {
+    match (element, attr) {
+        ("a", "href") => value.find(':').map_or(false, |colon| {
+            ALLOWED_LINK_SCHEMES.iter().any(|scheme| value[..colon].eq_ignore_ascii_case(scheme))
+        }),
+        ("img", "src") => value.starts_with("mxc://"),
+        ("code", "class") => value.starts_with("language-"),
+        _ => true,
+    }
+}
+
+/// Sanitizes the given HTML according to the allow-list of `config` and writes the result to
+/// `out`.
+///
+/// The input is processed as a stream of tokens, so only the stack of currently open elements is
+/// kept in memory. Disallowed elements are removed but their content is kept, except for
+/// `<script>` and `<style>` elements which are removed entirely. Disallowed attributes and
+/// comments are removed, and all elements that are still open at the end are closed.
+pub(super) fn sanitize(
+    input: &str,
+    config: &SanitizerConfig,
+    out: &mut impl fmt::Write,
+) -> fmt::Result {
+    let mut open_elements: Vec<String> = Vec::new();
+    let mut dropped_element: Option<String> = None;
+    // The number of open elements that were skipped because they are nested too deeply. Their end
+    // tags must not close the elements they are nested in.
+    let mut skipped_depth = 0_usize;
+
+    for token in Tokenizer::new(input) {
+        if let Some(dropped) = &dropped_element {
+            if matches!(&token, Token::EndTag { name } if name == dropped) {
+                dropped_element = None;
+            }
+            continue;
+        }
+
+        match token {
+            Token::StartTag { name, attrs, self_closing } => {
+                if DROPPED_CONTENT_ELEMENTS.contains(&name.as_str()) {
+                    if !self_closing {
+                        dropped_element = Some(name);
+                    }
+                    continue;
+                }
+
+                if !config.is_allowed_element(&name) {
+                    continue;
+                }
+
+                let is_void = is_void_element(&name);
+                if !is_void && open_elements.len() >= MAX_DEPTH {
+                    if !self_closing {
+                        skipped_depth += 1;
+                    }
+                    continue;
+                }
+
+                write!(out, "<{}", name)?;
+                for (attr, value) in &attrs {
+                    if config.is_allowed_attribute(&name, attr)
+                        && is_allowed_attr_value(&name, attr, value)
+                    {
+                        write!(out, " {}=\"{}\"", attr, escape_attr(value))?;
+                    }
+                }
+
+                if is_void {
+                    out.write_str(" />")?;
+                } else if self_closing {
+                    write!(out, "></{}>", name)?;
+                } else {
+                    out.write_char('>')?;
+                    open_elements.push(name);
+                }
+            }
+            Token::EndTag { name } => {
+                if skipped_depth > 0 && config.is_allowed_element(&name) && !is_void_element(&name)
+                {
+                    skipped_depth -= 1;
+                } else if let Some(pos) = open_elements.iter().rposition(|open| *open == name) {
+                    for open in open_elements.drain(pos..).rev() {
+                        write!(out, "</{}>", open)?;
+                    }
+                }
+            }
+            Token::Text(text) => out.write_str(&escape_text(&decode_entities(text)))?,
+            Token::Comment(_) => {}
+        }
+    }
+
+    for open in open_elements.into_iter().rev() {
+        write!(out, "</{}>", open)?;
+    }
+
+    Ok(())
+}
+
+/// Converts the given HTML to plain text.
+///
+/// Markup is removed and character references are decoded. Line breaks and block elements like
+/// paragraphs start a new line. The content of `<script>` and `<style>` elements and of the rich
+/// reply fallback in `<mx-reply>` is removed.
+pub(super) fn plain_text(input: &str) -> String {
+    let mut text = String::with_capacity(input.len());
+    let mut dropped_element: Option<String> = None;
+
+    for token in Tokenizer::new(input) {
+        if let Some(dropped) = &dropped_element {
+            if matches!(&token, Token::EndTag { name } if name == dropped) {
+                dropped_element = None;
+            }
+            continue;
+        }
+
+        match token {
+            Token::StartTag { name, self_closing: false, .. }
+                if DROPPED_CONTENT_ELEMENTS.contains(&name.as_str()) || name == "mx-reply" =>
+            {
+                dropped_element = Some(name);
+            }
+            Token::StartTag { name, .. } if name == "br" => text.push('\n'),
+            Token::StartTag { name, .. } | Token::EndTag { name }
+                if BLOCK_ELEMENTS.contains(&name.as_str())
+                    && !text.is_empty()
+                    && !text.ends_with('\n') =>
+            {
+                text.push('\n');
+            }
+            Token::Text(t) => text.push_str(&decode_entities(t)),
+            _ => {}
+        }
+    }
+
+    let len = text.trim_end_matches('\n').len();
+    text.truncate(len);
+    text
+}
+
+/// Extracts the collapsible sections, i.e. `<details>` elements, of the given HTML.
+///
+/// Returns the text of the `<summary>` of each section and the HTML of the rest of its content.
+/// Sections nested in another section are kept in the HTML of the outer section.
+pub(super) fn collapsible_sections(input: &str) -> Vec<(String, String)> {
+    let mut sections = Vec::new();
+    let mut current_section: Option<(String, String)> = None;
+    let mut details_depth = 0_usize;
+    let mut in_summary = false;
+    let mut tokenizer = Tokenizer::new(input);
+
+    loop {
+        let start = tokenizer.pos;
+        let token = match tokenizer.next() {
+            Some(token) => token,
+            None => break,
+        };
+        let raw = &input[start..tokenizer.pos];
+
+        let (summary, details) = match &mut current_section {
+            Some(section) => section,
+            None => {
+                if matches!(&token, Token::StartTag { name, .. } if name == "details") {
+                    current_section = Some((String::new(), String::new()));
+                    details_depth = 1;
+                }
+                continue;
+            }
+        };
+
+        match &token {
+            Token::StartTag { name, .. } if name == "summary" && details_depth == 1 => {
+                in_summary = true;
+            }
+            Token::EndTag { name } if name == "summary" && in_summary => in_summary = false,
+            Token::Text(text) if in_summary => summary.push_str(&decode_entities(text)),
+            _ if in_summary => {}
+            Token::EndTag { name } if name == "details" && details_depth == 1 => {
+                let (summary, details) = current_section.take().unwrap();
+                sections.push((summary.trim().to_owned(), details.trim().to_owned()));
+                details_depth = 0;
+            }
+            _ => {
+                match &token {
+                    Token::StartTag { name, .. } if name == "details" => details_depth += 1,
+                    Token::EndTag { name } if name == "details" => details_depth -= 1,
+                    _ => {}
+                }
+                details.push_str(raw);
+            }
+        }
+    }
+
+    if let Some((summary, details)) = current_section {
+        sections.push((summary.trim().to_owned(), details.trim().to_owned()));
+    }
+
+    sections
+}
+
+/// Rewrites the shorthand `matrix.to` links in the `href` attributes of `<a>` elements of the
+/// given HTML into absolute links starting with `base`.
+///
+/// Shorthand links are protocol-relative links like `//matrix.to/#/@user:example.org`, links
+/// without a scheme like `matrix.to/#/@user:example.org` and bare identifiers like
+/// `#room:example.org`. All other markup is kept as-is.
+pub(super) fn absolutize_matrix_links(input: &str, base: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut tokenizer = Tokenizer::new(input);
+
+    loop {
+        let start = tokenizer.pos;
+        let token = match tokenizer.next() {
+            Some(token) => token,
+            None => break,
+        };
+        let raw = &input[start..tokenizer.pos];
+
+        match token {
+            Token::StartTag { name, mut attrs, self_closing } if name == "a" => {
+                let mut rewritten = false;
+                for (attr, value) in &mut attrs {
+                    if attr == "href" {
+                        if let Some(link) = absolute_matrix_link(value, base) {
+                            *value = link;
+                            rewritten = true;
+                        }
+                    }
+                }
+
+                if !rewritten {
+                    output.push_str(raw);
+                    continue;
+                }
+
+                output.push_str("<a");
+                for (attr, value) in &attrs {
+                    output.push_str(&format!(" {}=\"{}\"", attr, escape_attr(value)));
+                }
+                output.push_str(if self_closing { " />" } else { ">" });
+            }
+            _ => output.push_str(raw),
+        }
+    }
+
+    output
+}
+
+/// Converts the given shorthand `matrix.to` link to an absolute link starting with `base`.
+///
+/// Returns `None` if `href` is not a shorthand link.
+fn absolute_matrix_link(href: &str, base: &str) -> Option<String> {
+    let base = base.trim_end_matches('/');
+
+    for prefix in &["//matrix.to/", "matrix.to/"] {
+        if href.starts_with(prefix) {
+            return Some(format!("{}{}", base, &href[prefix.len() - 1..]));
+        }
+    }
+
+    let is_identifier = href.starts_with(&['@', '#', '!', '$', '+'][..])
+        && href.contains(':')
+        && !href.contains(char::is_whitespace);
+    if is_identifier {
+        Some(format!("{}/#/{}", base, href.replace('#', "%23")))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        absolutize_matrix_links, collapsible_sections, decode_entities, plain_text, sanitize,
+        SanitizerConfig, Token, Tokenizer, MAX_DEPTH,
+    };
+
+    #[test]
+    fn tokenize() {
+        let tokens: Vec<_> =
+            Tokenizer::new(r#"<p class='a'>1 < 2<br/><!-- c --><A HREF="x&amp;y">z</a></p>"#)
+                .collect();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::StartTag {
+                    name: "p".into(),
+                    attrs: vec![("class".into(), "a".into())],
+                    self_closing: false,
+                },
+                Token::Text("1 "),
+                Token::Text("< 2"),
+                Token::StartTag { name: "br".into(), attrs: vec![], self_closing: true },
+                Token::Comment("<!-- c -->"),
+                Token::StartTag {
+                    name: "a".into(),
+                    attrs: vec![("href".into(), "x&y".into())],
+                    self_closing: false,
+                },
+                Token::Text("z"),
+                Token::EndTag { name: "a".into() },
+                Token::EndTag { name: "p".into() },
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_tag_is_text() {
+        let tokens: Vec<_> = Tokenizer::new("a <b c=\"d").collect();
+        assert_eq!(tokens, vec![Token::Text("a "), Token::Text("<b c=\"d")]);
+    }
+
+    #[test]
+    fn entities() {
+        assert_eq!(
+            decode_entities("&lt;b&gt; &amp;amp; &#x1F600; &#65; &copy; & x"),
+            "<b> &amp; 😀 A &copy; & x"
+        );
+    }
+
+    fn sanitized(input: &str) -> String {
+        sanitized_with(input, &SanitizerConfig::default())
+    }
+
+    fn sanitized_with(input: &str, config: &SanitizerConfig) -> String {
+        let mut out = String::new();
+        sanitize(input, config, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn sanitize_allowed() {
+        assert_eq!(
+            sanitized(r#"<p>Hello, <a href="https://example.org" onclick="x()">World</a>!</p>"#),
+            r#"<p>Hello, <a href="https://example.org">World</a>!</p>"#
+        );
+        assert_eq!(
+            sanitized(r#"<pre><code class="language-rust">fn main() {}</code></pre><br>"#),
+            r#"<pre><code class="language-rust">fn main() {}</code></pre><br />"#
+        );
+    }
+
+    #[test]
+    fn sanitize_disallowed() {
+        assert_eq!(
+            sanitized(
+                r#"<marquee>Hi</marquee><script>alert("x")</script><!-- c --><img src="https://example.org/a.png">"#
+            ),
+            "Hi<img />"
+        );
+        assert_eq!(sanitized(r#"<a href="javascript:alert(1)">link</a>"#), "<a>link</a>");
+    }
+
+    #[test]
+    fn sanitize_malformed() {
+        assert_eq!(
+            sanitized("<b><i>unclosed</b> 1 < 2 &amp; <u>x"),
+            "<b><i>unclosed</i></b> 1 &lt; 2 &amp; <u>x</u>"
+        );
+        assert_eq!(sanitized("</p>stray end<p/>"), "stray end<p></p>");
+        assert_eq!(
+            sanitized(&"<div>".repeat(MAX_DEPTH + 1)),
+            "<div>".repeat(MAX_DEPTH) + &"</div>".repeat(MAX_DEPTH)
+        );
+    }
+
+    #[test]
+    fn sanitize_beyond_max_depth() {
+        // Like with a document tree, the elements nested too deeply are removed without affecting
+        // the elements they are nested in.
+        let input = "<b>".repeat(MAX_DEPTH)
+            + "<b>deep <i>deeper</i> <br>x</b> after"
+            + &"</b>".repeat(MAX_DEPTH)
+            + " outside";
+        let tree = "<b>".repeat(MAX_DEPTH)
+            + "deep deeper <br />x after"
+            + &"</b>".repeat(MAX_DEPTH)
+            + " outside";
+
+        assert_eq!(sanitized(&input), tree);
+    }
+
+    #[test]
+    fn sanitize_extra_elements() {
+        let config = SanitizerConfig::new()
+            .allow_element("details", &["open"])
+            .allow_element("summary", &[]);
+
+        assert_eq!(
+            sanitized_with(
+                r#"<details open="" class="x"><summary>More</summary><marquee>Hidden</marquee></details>"#,
+                &config
+            ),
+            r#"<details open=""><summary>More</summary>Hidden</details>"#
+        );
+        assert_eq!(sanitized("<details><summary>More</summary></details>"), "More");
+    }
+
+    #[test]
+    fn to_plain_text() {
+        assert_eq!(
+            plain_text("<p>Hello <b>world</b> &amp; all</p><p>Line<br>break</p><ul><li>a</li><li>b</li></ul>"),
+            "Hello world & all\nLine\nbreak\na\nb"
+        );
+        assert_eq!(
+            plain_text(
+                "<mx-reply><blockquote>Quoted</blockquote></mx-reply>Answer<script>x()</script>"
+            ),
+            "Answer"
+        );
+    }
+
+    #[test]
+    fn absolutize_links() {
+        assert_eq!(
+            absolutize_matrix_links(
+                r#"<a href='//matrix.to/#/@alice:example.org'>Alice</a> and <b>Bob</b>"#,
+                "https://matrix.to"
+            ),
+            r#"<a href="https://matrix.to/#/@alice:example.org">Alice</a> and <b>Bob</b>"#
+        );
+        assert_eq!(
+            absolutize_matrix_links(
+                r#"<A HREF=https://example.org>link</A> <a href="$event:example.org">event</a>"#,
+                "https://matrix.to/"
+            ),
+            r#"<A HREF=https://example.org>link</A> <a href="https://matrix.to/#/$event:example.org">event</a>"#
+        );
+    }
+
+    #[test]
+    fn nested_collapsible_sections() {
+        assert_eq!(
+            collapsible_sections(
+                "<details><summary>Outer</summary><details><summary>Inner</summary>x</details>\
+                 </details><p>After</p><details><summary>Unclosed &amp; open</summary>y"
+            ),
+            vec![
+                ("Outer".to_owned(), "<details><summary>Inner</summary>x</details>".to_owned()),
+                ("Unclosed & open".to_owned(), "y".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn sanitize_collapsible_sections() {
+        let input = "<details open><summary>More</summary><p>Hidden</p></details>";
+        let mut output = String::new();
+
+        sanitize(input, &SanitizerConfig::new(), &mut output).unwrap();
+        assert_eq!(output, "More<p>Hidden</p>");
+
+        output.clear();
+        sanitize(input, &SanitizerConfig::new().allow_collapsible_sections(), &mut output).unwrap();
+        assert_eq!(output, r#"<details open=""><summary>More</summary><p>Hidden</p></details>"#);
+    }
+}
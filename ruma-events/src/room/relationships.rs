@@ -6,13 +6,15 @@
 //! MSC for all the relates_to types except replies:
 //!     https://github.com/matrix-org/matrix-doc/pull/2674
 
+use std::collections::BTreeMap;
+
 use ruma_identifiers::EventId;
-use serde::{Deserialize, Serialize};
+use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
 use serde_json::Value as JsonValue;
 
 /// Enum modeling the different ways relationships can be expressed in a
 /// `m.relates_to` field of an event.
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize)]
 #[serde(untagged)]
 pub(crate) enum RelatesToJsonRepr {
     /// A relation which contains subtypes indicating the type of the
@@ -25,12 +27,39 @@ pub(crate) enum RelatesToJsonRepr {
         /// Information about another message being replied to.
         #[serde(rename = "m.in_reply_to")]
         in_reply_to: InReplyTo,
+
+        /// Unknown keys next to `m.in_reply_to`, kept so they can be re-emitted.
+        #[serde(flatten)]
+        custom: BTreeMap<String, JsonValue>,
     },
 
     /// Custom, unsupported relationship.
     Custom(JsonValue),
 }
 
+// `Serialize` is implemented manually because the derived implementation for the flattened
+// `custom` keys of replies doesn't know the number of keys upfront, which formats like
+// MessagePack need.
+impl Serialize for RelatesToJsonRepr {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Self::Relation(relation) => relation.serialize(serializer),
+            Self::Reply { in_reply_to, custom } => {
+                let mut map = serializer.serialize_map(Some(1 + custom.len()))?;
+                map.serialize_entry("m.in_reply_to", in_reply_to)?;
+                for (key, value) in custom {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+            Self::Custom(custom) => custom.serialize(serializer),
+        }
+    }
+}
+
 /// A relation, which associates new information to an existing event.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(tag = "rel_type")]
@@ -48,6 +77,11 @@ pub(crate) enum RelationJsonRepr {
     #[cfg(feature = "unstable-pre-spec")]
     #[serde(rename = "m.replace")]
     Replacement(Replacement),
+
+    /// An event that belongs to a thread.
+    #[cfg(feature = "unstable-pre-spec")]
+    #[serde(rename = "m.thread")]
+    Thread(Thread),
 }
 
 /// Information about the event a "rich reply" is replying to.
@@ -55,6 +89,22 @@ pub(crate) enum RelationJsonRepr {
 pub struct InReplyTo {
     /// The event being replied to.
     pub event_id: EventId,
+
+    /// Where a thread-aware client should render this reply, e.g. `m.thread`.
+    #[cfg(feature = "unstable-pre-spec")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub render_in: Option<Vec<String>>,
+}
+
+impl InReplyTo {
+    /// Creates a new `InReplyTo` with the given event ID.
+    pub fn new(event_id: EventId) -> Self {
+        Self {
+            event_id,
+            #[cfg(feature = "unstable-pre-spec")]
+            render_in: None,
+        }
+    }
 }
 
 /// A reference to another event.
@@ -75,6 +125,18 @@ pub struct Annotation {
     pub key: String,
 }
 
+impl Annotation {
+    /// The ID of the annotated event.
+    pub fn event_id(&self) -> &EventId {
+        &self.event_id
+    }
+
+    /// The key of the annotation, e.g. the emoji of a reaction.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
 /// An event replacing another event.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[cfg(feature = "unstable-pre-spec")]
@@ -83,12 +145,58 @@ pub struct Replacement {
     pub event_id: EventId,
 }
 
+/// The content of a thread relation.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg(feature = "unstable-pre-spec")]
+pub struct Thread {
+    /// The ID of the root message in the thread.
+    pub event_id: EventId,
+
+    /// A reply relation.
+    ///
+    /// If this event is a reply and belongs to a thread, this points to the message that is being
+    /// replied to, and `is_falling_back` must be set to `false`.
+    ///
+    /// If this event is not a reply, this is used as a fallback mechanism for clients that do not
+    /// support threads. This should point to the latest message-like event in the thread and
+    /// `is_falling_back` must be set to `true`.
+    #[serde(rename = "m.in_reply_to", skip_serializing_if = "Option::is_none")]
+    pub in_reply_to: Option<InReplyTo>,
+
+    /// Whether the `m.in_reply_to` field is a fallback for older clients or a genuine reply in a
+    /// thread.
+    #[serde(default, skip_serializing_if = "ruma_serde::is_default")]
+    pub is_falling_back: bool,
+}
+
+#[cfg(feature = "unstable-pre-spec")]
+impl Thread {
+    /// Convenience method to create a regular `Thread` with the given event ID and latest
+    /// message-like event ID.
+    ///
+    /// The reply to the latest event is only a fallback for clients that don't support threads,
+    /// so `is_falling_back` is set to `true`.
+    pub fn plain(event_id: EventId, latest_event_id: EventId) -> Self {
+        Self { event_id, in_reply_to: Some(InReplyTo::new(latest_event_id)), is_falling_back: true }
+    }
+
+    /// Convenience method to create a `Thread` that is a genuine reply to another event in the
+    /// thread with the given event ID.
+    pub fn reply(event_id: EventId, reply_to_event_id: EventId) -> Self {
+        Self {
+            event_id,
+            in_reply_to: Some(InReplyTo::new(reply_to_event_id)),
+            is_falling_back: false,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::room::message::Relation;
+    use crate::room::message::{MessageEventContent, Relation};
     use matches::assert_matches;
     use ruma_identifiers::event_id;
-    use serde_json::{from_value as from_json_value, json};
+    use serde_json::{from_value as from_json_value, json, to_value as to_json_value};
 
     #[test]
     fn reply_deserialize() {
@@ -102,11 +210,29 @@ mod test {
 
         assert_matches!(
             from_json_value::<Relation>(json).unwrap(),
-            Relation::Reply { in_reply_to }
-            if in_reply_to.event_id == event_id
+            Relation::Reply { in_reply_to, custom }
+            if in_reply_to.event_id == event_id && custom.is_empty()
         );
     }
 
+    #[test]
+    fn reply_unknown_keys_roundtrip() {
+        let json = json!({
+            "m.in_reply_to": {
+                "event_id": "$1598361704261elfgc:localhost",
+            },
+            "com.example.x": { "foo": "bar" },
+        });
+
+        let relation = from_json_value::<Relation>(json.clone()).unwrap();
+        assert_matches!(
+            &relation,
+            Relation::Reply { custom, .. }
+            if custom.get("com.example.x") == Some(&json!({ "foo": "bar" }))
+        );
+        assert_eq!(to_json_value(relation).unwrap(), json);
+    }
+
     #[test]
     #[cfg(feature = "unstable-pre-spec")]
     fn reference_deserialize() {
@@ -157,4 +283,187 @@ mod test {
             if annotation.event_id == event_id && annotation.key == "🦛"
         );
     }
+
+    #[test]
+    fn known_rel_type_takes_precedence_over_reply() {
+        let json = json!({
+            "rel_type": "m.annotation",
+            "event_id": "$annotated:localhost",
+            "key": "🦛",
+            "m.in_reply_to": {
+                "event_id": "$replied:localhost",
+            },
+        });
+
+        assert_matches!(
+            from_json_value::<Relation>(json).unwrap(),
+            Relation::Annotation(annotation)
+            if annotation.event_id == event_id!("$annotated:localhost")
+        );
+    }
+
+    #[test]
+    fn reply_takes_precedence_over_unknown_rel_type() {
+        let json = json!({
+            "rel_type": "com.example.custom",
+            "event_id": "$custom:localhost",
+            "m.in_reply_to": {
+                "event_id": "$replied:localhost",
+            },
+        });
+
+        assert_matches!(
+            from_json_value::<Relation>(json).unwrap(),
+            Relation::Reply { in_reply_to, custom }
+            if in_reply_to.event_id == event_id!("$replied:localhost")
+                && custom.get("rel_type") == Some(&json!("com.example.custom"))
+                && custom.get("event_id") == Some(&json!("$custom:localhost"))
+        );
+    }
+
+    #[test]
+    fn duplicate_relates_to_is_an_error() {
+        let json = r#"{
+            "body": "test",
+            "msgtype": "m.text",
+            "m.relates_to": { "m.in_reply_to": { "event_id": "$first:localhost" } },
+            "m.relates_to": { "m.in_reply_to": { "event_id": "$second:localhost" } }
+        }"#;
+
+        assert!(serde_json::from_str::<MessageEventContent>(json).is_err());
+    }
+
+    #[test]
+    fn annotation_accessors() {
+        let json = json!({
+            "rel_type": "m.annotation",
+            "event_id": "$1598361704261elfgc:localhost",
+            "key": "🦛",
+        });
+
+        let relation = from_json_value::<Relation>(json).unwrap();
+        let annotation = relation.as_annotation().unwrap();
+        assert_eq!(annotation.event_id(), &event_id!("$1598361704261elfgc:localhost"));
+        assert_eq!(annotation.key(), "🦛");
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn thread_fallback_serialize_deserialize() {
+        use super::Thread;
+
+        let root = event_id!("$root:localhost");
+        let latest = event_id!("$latest:localhost");
+
+        let relation = Relation::Thread(Thread::plain(root.clone(), latest.clone()));
+        let json = json!({
+            "rel_type": "m.thread",
+            "event_id": root,
+            "m.in_reply_to": {
+                "event_id": latest,
+            },
+            "is_falling_back": true,
+        });
+        assert_eq!(to_json_value(&relation).unwrap(), json);
+
+        assert_matches!(
+            from_json_value::<Relation>(json).unwrap(),
+            Relation::Thread(Thread { event_id, in_reply_to: Some(in_reply_to), is_falling_back })
+            if event_id == root && in_reply_to.event_id == latest && is_falling_back
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn in_reply_to_without_render_in() {
+        use super::InReplyTo;
+
+        let event_id = event_id!("$1598361704261elfgc:localhost");
+        let json = json!({ "event_id": event_id });
+
+        assert_matches!(
+            from_json_value::<InReplyTo>(json.clone()).unwrap(),
+            InReplyTo { event_id: id, render_in: None } if id == event_id
+        );
+        assert_eq!(to_json_value(InReplyTo::new(event_id)).unwrap(), json);
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn in_reply_to_with_render_in() {
+        use super::InReplyTo;
+
+        let event_id = event_id!("$1598361704261elfgc:localhost");
+        let json = json!({ "event_id": event_id, "render_in": ["m.thread"] });
+
+        let in_reply_to = from_json_value::<InReplyTo>(json.clone()).unwrap();
+        assert_eq!(in_reply_to.render_in, Some(vec!["m.thread".to_owned()]));
+        assert_eq!(to_json_value(in_reply_to).unwrap(), json);
+    }
+
+    #[test]
+    fn custom_relation_with_nested_reply_roundtrip() {
+        let relates_to = json!({
+            "rel_type": "org.example.nested",
+            "event_id": "$1598361704261elfgc:localhost",
+            "m.in_reply_to": {
+                "event_id": "$latest:localhost",
+            },
+            "org.example.data": {
+                "levels": [1, 2, { "deeper": [true, null, "text"] }],
+                "nested": { "m.in_reply_to": { "event_id": "$other:localhost" } },
+            },
+        });
+
+        let relation = Relation::custom(relates_to.clone());
+        assert_eq!(to_json_value(&relation).unwrap(), relates_to);
+
+        let content =
+            to_json_value(MessageEventContent::text_plain("Hello").with_relation(Some(relation)))
+                .unwrap();
+        assert_eq!(content["m.relates_to"], relates_to);
+
+        let roundtripped = from_json_value::<MessageEventContent>(content).unwrap();
+        assert_eq!(to_json_value(roundtripped.relates_to()).unwrap(), relates_to);
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn thread_with_fallback_reply() {
+        let json = json!({
+            "rel_type": "m.thread",
+            "event_id": "$root:localhost",
+            "m.in_reply_to": { "event_id": "$latest:localhost" },
+            "is_falling_back": true,
+        });
+
+        let relation = from_json_value::<Relation>(json.clone()).unwrap();
+        assert_eq!(
+            relation.thread_fallback_reply().map(|reply| &reply.event_id),
+            Some(&event_id!("$latest:localhost"))
+        );
+        assert_eq!(to_json_value(&relation).unwrap(), json);
+
+        let reply = relation.thread_fallback_reply().unwrap();
+        assert_eq!(to_json_value(reply).unwrap(), json!({ "event_id": "$latest:localhost" }));
+    }
+
+    #[test]
+    #[cfg(feature = "unstable-pre-spec")]
+    fn thread_without_fallback_reply() {
+        let genuine_reply = from_json_value::<Relation>(json!({
+            "rel_type": "m.thread",
+            "event_id": "$root:localhost",
+            "m.in_reply_to": { "event_id": "$other:localhost" },
+        }))
+        .unwrap();
+        assert!(genuine_reply.thread_fallback_reply().is_none());
+
+        let no_reply = from_json_value::<Relation>(json!({
+            "rel_type": "m.thread",
+            "event_id": "$root:localhost",
+        }))
+        .unwrap();
+        assert!(no_reply.thread_fallback_reply().is_none());
+    }
 }
@@ -0,0 +1,114 @@
+//! Message contents shared by the round-trip tests for the binary formats.
+
+use js_int::uint;
+use ruma_events::room::{
+    message::{
+        AudioMessageEventContent, EmoteMessageEventContent, FileMessageEventContent,
+        ImageMessageEventContent, LimitType, LocationMessageEventContent, MessageEventContent,
+        NoticeMessageEventContent, ServerNoticeMessageEventContent, ServerNoticeType,
+        TextMessageEventContent, VideoInfo, VideoMessageEventContent,
+    },
+    ImageInfo,
+};
+
+pub fn audio() -> MessageEventContent {
+    MessageEventContent::Audio(AudioMessageEventContent {
+        body: "test.ogg".into(),
+        #[cfg(feature = "unstable-pre-spec")]
+        filename: None,
+        info: None,
+        url: Some("mxc://example.org/audio".into()),
+        file: None,
+    })
+}
+
+pub fn emote() -> MessageEventContent {
+    MessageEventContent::Emote(EmoteMessageEventContent::html("waves", "<em>waves</em>"))
+}
+
+pub fn file() -> MessageEventContent {
+    MessageEventContent::File(FileMessageEventContent {
+        body: "document.pdf".into(),
+        filename: Some("document.pdf".into()),
+        info: None,
+        url: Some("mxc://example.org/file".into()),
+        file: None,
+    })
+}
+
+pub fn image() -> MessageEventContent {
+    MessageEventContent::Image(ImageMessageEventContent {
+        body: "sunset.png".into(),
+        #[cfg(feature = "unstable-pre-spec")]
+        filename: None,
+        #[cfg(feature = "unstable-pre-spec")]
+        sticker_like: false,
+        info: Some(Box::new(ImageInfo {
+            height: Some(uint!(480)),
+            width: Some(uint!(640)),
+            mimetype: Some("image/png".into()),
+            size: Some(uint!(1024)),
+            thumbnail_info: None,
+            thumbnail_url: None,
+            thumbnail_file: None,
+            #[cfg(feature = "unstable-pre-spec")]
+            blurhash: None,
+        })),
+        url: Some("mxc://example.org/image".into()),
+        file: None,
+    })
+}
+
+pub fn location() -> MessageEventContent {
+    MessageEventContent::Location(LocationMessageEventContent::new(
+        "Big Ben, London, UK",
+        "geo:51.5008,0.1247",
+    ))
+}
+
+pub fn notice() -> MessageEventContent {
+    MessageEventContent::Notice(NoticeMessageEventContent::plain("Beep boop"))
+}
+
+pub fn server_notice() -> MessageEventContent {
+    MessageEventContent::ServerNotice(ServerNoticeMessageEventContent {
+        body: "Usage limit reached".into(),
+        server_notice_type: ServerNoticeType::UsageLimitReached,
+        admin_contact: Some("mailto:admin@example.org".into()),
+        limit_type: Some(LimitType::MonthlyActiveUser),
+        relates_to: None,
+    })
+}
+
+pub fn text_html() -> MessageEventContent {
+    MessageEventContent::Text(TextMessageEventContent::html(
+        "Hello, World!",
+        "Hello, <em>World</em>!",
+    ))
+}
+
+pub fn text_plain() -> MessageEventContent {
+    MessageEventContent::text_plain("Hello, World!")
+}
+
+pub fn video() -> MessageEventContent {
+    MessageEventContent::Video(VideoMessageEventContent {
+        body: "clip.mp4".into(),
+        #[cfg(feature = "unstable-pre-spec")]
+        filename: None,
+        info: Some(Box::new(VideoInfo {
+            duration: Some(uint!(1000)),
+            height: None,
+            width: None,
+            mimetype: Some("video/mp4".into()),
+            size: None,
+            thumbnail_info: None,
+            thumbnail_url: Some("mxc://example.org/thumbnail".into()),
+            thumbnail_file: None,
+            #[cfg(feature = "unstable-pre-spec")]
+            blurhash: None,
+        })),
+        url: Some("mxc://example.org/video".into()),
+        file: None,
+    })
+}
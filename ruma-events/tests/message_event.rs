@@ -7,7 +7,7 @@ use ruma_events::{
     call::{answer::AnswerEventContent, SessionDescription, SessionDescriptionType},
     room::{ImageInfo, ThumbnailInfo},
     sticker::StickerEventContent,
-    AnyMessageEventContent, AnySyncMessageEvent, MessageEvent, RawExt, Unsigned,
+    AnyMessageEventContent, AnySyncMessageEvent, MessageEvent, RawExt, SyncMessageEvent, Unsigned,
 };
 use ruma_identifiers::{event_id, room_id, user_id};
 use serde_json::{from_value as from_json_value, json, to_value as to_json_value};
@@ -30,6 +30,8 @@ fn message_serialize_sticker() {
                 })),
                 thumbnail_url: Some("mxc://matrix.org".into()),
                 thumbnail_file: None,
+                #[cfg(feature = "unstable-pre-spec")]
+                blurhash: None,
             },
             url: "http://www.matrix.org".into(),
         }),
@@ -189,6 +191,7 @@ fn deserialize_message_sticker() {
                     thumbnail_info: Some(thumbnail_info),
                     thumbnail_url: Some(thumbnail_url),
                     thumbnail_file: None,
+                    ..
                 },
                 url,
             }),
@@ -280,3 +283,112 @@ fn deserialize_message_then_convert_to_full() {
             && unsigned.is_empty()
     );
 }
+
+#[test]
+fn unsigned_flag_preserves_other_unsigned_fields() {
+    let json_data = json!({
+        "content": {
+            "answer": {
+                "type": "answer",
+                "sdp": "Hello"
+            },
+            "call_id": "foofoo",
+            "version": 1
+        },
+        "event_id": "$h29iv0s8:example.com",
+        "origin_server_ts": 1,
+        "room_id": "!roomid:room.com",
+        "sender": "@carl:example.com",
+        "type": "m.call.answer",
+        "unsigned": {
+            "age": 5,
+            "transaction_id": "txn1",
+            "m.relations": { "m.annotation": { "chunk": [] } },
+            "com.example.other": "keep me"
+        }
+    });
+
+    let mut event = from_json_value::<Raw<MessageEvent<AnyMessageEventContent>>>(json_data)
+        .unwrap()
+        .deserialize()
+        .unwrap();
+
+    assert_eq!(event.unsigned_flag("com.example.marked_unread"), None);
+
+    event.set_unsigned_flag("com.example.marked_unread", true);
+    assert_eq!(event.unsigned_flag("com.example.marked_unread"), Some(true));
+
+    let unsigned = to_json_value(&event).unwrap()["unsigned"].clone();
+    assert_eq!(
+        unsigned,
+        json!({
+            "age": 5,
+            "transaction_id": "txn1",
+            "m.relations": { "m.annotation": { "chunk": [] } },
+            "com.example.marked_unread": true,
+            "com.example.other": "keep me"
+        })
+    );
+
+    assert_eq!(event.remove_unsigned_flag("com.example.other"), None);
+    assert_eq!(event.remove_unsigned_flag("com.example.marked_unread"), Some(true));
+    assert_eq!(event.unsigned_flag("com.example.marked_unread"), None);
+    assert_eq!(event.unsigned.custom.get("com.example.other"), Some(&json!("keep me")));
+}
+
+#[test]
+fn sync_unsigned_flag_ignores_specified_unsigned_fields() {
+    let json_data = json!({
+        "content": {
+            "answer": {
+                "type": "answer",
+                "sdp": "Hello"
+            },
+            "call_id": "foofoo",
+            "version": 1
+        },
+        "event_id": "$h29iv0s8:example.com",
+        "origin_server_ts": 1,
+        "sender": "@carl:example.com",
+        "type": "m.call.answer",
+        "unsigned": {
+            "age": 5,
+            "prev_content": {},
+            "com.example.marked_unread": true
+        }
+    });
+
+    let mut event = from_json_value::<Raw<SyncMessageEvent<AnyMessageEventContent>>>(json_data)
+        .unwrap()
+        .deserialize()
+        .unwrap();
+
+    assert_eq!(event.unsigned_flag("com.example.marked_unread"), Some(true));
+    assert_eq!(event.unsigned.custom.len(), 1);
+
+    event.set_unsigned_flag("com.example.marked_unread", false);
+    assert_eq!(
+        to_json_value(&event).unwrap()["unsigned"],
+        json!({
+            "age": 5,
+            "com.example.marked_unread": false
+        })
+    );
+
+    assert_eq!(event.remove_unsigned_flag("com.example.marked_unread"), Some(false));
+    assert!(!event.unsigned.is_empty());
+    event.unsigned.age = None;
+    assert!(event.unsigned.is_empty());
+}
+
+#[test]
+fn unsigned_without_unknown_fields_is_empty() {
+    let unsigned = from_json_value::<Unsigned>(json!({
+        "prev_content": { "membership": "join" },
+        "redacted_because": {}
+    }))
+    .unwrap();
+
+    assert!(unsigned.custom.is_empty());
+    assert!(unsigned.is_empty());
+}
@@ -0,0 +1,131 @@
+//! Round-trip tests for `m.room.message` content through MessagePack, a binary format that is
+//! still self-describing, to catch serde attributes that only work with JSON.
+
+use maplit::btreemap;
+use ruma_events::room::message::{
+    EmoteMessageEventContent, FormattedBody, InReplyTo, MessageEventContent,
+    NoticeMessageEventContent, Relation, TextMessageEventContent,
+};
+#[cfg(feature = "unstable-pre-spec")]
+use ruma_events::room::message::{Mentions, UrlPreview};
+use ruma_identifiers::event_id;
+use serde_json::{json, to_value as to_json_value};
+
+mod common;
+
+/// Serializes the given content to MessagePack and deserializes it again.
+fn round_trip(content: &MessageEventContent) -> MessageEventContent {
+    let bytes = rmp_serde::to_vec_named(content).unwrap();
+    rmp_serde::from_slice(&bytes).unwrap()
+}
+
+fn assert_round_trip(content: MessageEventContent) {
+    let round_tripped = round_trip(&content);
+
+    assert_eq!(to_json_value(&round_tripped).unwrap(), to_json_value(&content).unwrap());
+}
+
+/// Like `assert_round_trip`, but compares the contents themselves instead of their JSON, so a field
+/// that is missing from a hand-written `Serialize` implementation isn't serialized away on both
+/// sides.
+///
+/// The contents passed to this are built without `..`, so that adding a field to them doesn't
+/// compile until it is covered here too.
+fn assert_all_fields_round_trip(content: MessageEventContent) {
+    let round_tripped = round_trip(&content);
+
+    assert_eq!(format!("{:?}", round_tripped), format!("{:?}", content));
+}
+
+fn reply() -> Relation {
+    Relation::Reply {
+        in_reply_to: InReplyTo::new(event_id!("$original:example.org")),
+        custom: btreemap! { "org.example.quote".into() => json!(true) },
+    }
+}
+
+#[test]
+fn audio() {
+    assert_round_trip(common::audio());
+}
+
+#[test]
+fn emote() {
+    assert_round_trip(common::emote());
+}
+
+#[test]
+fn file() {
+    assert_round_trip(common::file());
+}
+
+#[test]
+fn image() {
+    assert_round_trip(common::image());
+}
+
+#[test]
+fn location() {
+    assert_round_trip(common::location());
+}
+
+#[test]
+fn notice() {
+    assert_round_trip(common::notice());
+}
+
+#[test]
+fn server_notice() {
+    assert_round_trip(common::server_notice());
+}
+
+#[test]
+fn text() {
+    assert_round_trip(common::text_html());
+    assert_round_trip(common::text_plain());
+}
+
+#[test]
+fn video() {
+    assert_round_trip(common::video());
+}
+
+#[test]
+fn emote_all_fields() {
+    assert_all_fields_round_trip(MessageEventContent::Emote(EmoteMessageEventContent {
+        body: "waves".into(),
+        formatted: Some(FormattedBody::html("<em>waves</em>")),
+    }));
+}
+
+#[test]
+fn notice_all_fields() {
+    assert_all_fields_round_trip(MessageEventContent::Notice(NoticeMessageEventContent {
+        body: "Beep boop".into(),
+        formatted: Some(FormattedBody::html("<b>Beep</b> boop")),
+        relates_to: Some(reply()),
+        #[cfg(feature = "unstable-pre-spec")]
+        new_content: Some(Box::new(MessageEventContent::notice_plain("Boop beep"))),
+        #[cfg(feature = "unstable-pre-spec")]
+        mentions: Some(Mentions { room: true, ..Mentions::new() }),
+        #[cfg(feature = "unstable-pre-spec")]
+        lang: Some("en-US".into()),
+    }));
+}
+
+#[test]
+fn text_all_fields() {
+    assert_all_fields_round_trip(MessageEventContent::Text(TextMessageEventContent {
+        body: "Hello, World!".into(),
+        formatted: Some(FormattedBody::html("<b>Hello</b>, World!")),
+        relates_to: Some(reply()),
+        #[cfg(feature = "unstable-pre-spec")]
+        new_content: Some(Box::new(MessageEventContent::text_plain("Hello, everyone!"))),
+        #[cfg(feature = "unstable-pre-spec")]
+        mentions: Some(Mentions { room: true, ..Mentions::new() }),
+        #[cfg(feature = "unstable-pre-spec")]
+        lang: Some("en-US".into()),
+        #[cfg(feature = "unstable-pre-spec")]
+        url_previews: Some(vec![UrlPreview::new("https://example.org")]),
+    }));
+}
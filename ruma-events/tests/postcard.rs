@@ -0,0 +1,63 @@
+//! Round-trip tests for `m.room.message` content through postcard, a binary format that is not
+//! self-describing, using `BinaryMessageEventContent`.
+
+use ruma_events::room::message::{BinaryMessageEventContent, MessageEventContent};
+use serde_json::to_value as to_json_value;
+
+mod common;
+
+fn assert_round_trip(content: MessageEventContent) {
+    let json = to_json_value(&content).unwrap();
+
+    let bytes = postcard::to_allocvec(&BinaryMessageEventContent::from(content)).unwrap();
+    let round_tripped: MessageEventContent =
+        postcard::from_bytes::<BinaryMessageEventContent>(&bytes).unwrap().into();
+
+    assert_eq!(to_json_value(&round_tripped).unwrap(), json);
+}
+
+#[test]
+fn audio() {
+    assert_round_trip(common::audio());
+}
+
+#[test]
+fn emote() {
+    assert_round_trip(common::emote());
+}
+
+#[test]
+fn file() {
+    assert_round_trip(common::file());
+}
+
+#[test]
+fn image() {
+    assert_round_trip(common::image());
+}
+
+#[test]
+fn location() {
+    assert_round_trip(common::location());
+}
+
+#[test]
+fn notice() {
+    assert_round_trip(common::notice());
+}
+
+#[test]
+fn server_notice() {
+    assert_round_trip(common::server_notice());
+}
+
+#[test]
+fn text() {
+    assert_round_trip(common::text_html());
+    assert_round_trip(common::text_plain());
+}
+
+#[test]
+fn video() {
+    assert_round_trip(common::video());
+}
@@ -215,6 +215,7 @@ fn deserialize_avatar_without_prev_content() {
                     thumbnail_info: Some(thumbnail_info),
                     thumbnail_url: Some(thumbnail_url),
                     thumbnail_file: None,
+                    ..
                 } if *height == UInt::new(423)
                     && *width == UInt::new(1011)
                     && *mimetype == "image/png"